@@ -1,142 +1,289 @@
-use crate::models::{Transaction, RegionStats, MonthStats};
+use crate::models::{Transaction, RegionStats, DomainStats, PeriodStats, Period, Granularity, Dimension, GroupKey, GroupStats};
+use crate::money::Money;
 use std::collections::HashMap;
-use chrono::Datelike;
 
-// Compute statistics (total, count, avg, median) of all transactions
-// Loops over the transactions struct and push them into the hashmap for analysis
-pub fn compute_region_stats(transactions: &[Transaction]) -> Vec<RegionStats> {
-    let mut map: HashMap<String, Vec<u64>> = HashMap::new(); // Hashmap <Region, [total, avg, med, count]
+// Build the key of the group a transaction belongs to
+// No dimensions gives the same empty key for every row, i.e. one global group
+pub(crate) fn group_key(tx: &Transaction, dimensions: &[Dimension]) -> GroupKey {
+    let mut key = GroupKey { domain: None, location: None, period: None };
+    for dimension in dimensions {
+        match dimension {
+            Dimension::Domain => key.domain = Some(tx.domain.clone()),
+            Dimension::Location => key.location = Some(tx.location.clone()),
+            Dimension::Period(granularity) => key.period = Some(Period::containing(tx.date, *granularity)),
+        }
+    }
+    key
+}
+
+// Generic group-by over any combination of domain, location and time period
+// Takes one transaction at a time so a stream can be consumed without keeping the rows,
+// but the exact median needs every amount: 16 bytes per row and per accumulator stay in memory until finish()
+pub struct GroupAccumulator {
+    dimensions: Vec<Dimension>,
+    groups: HashMap<GroupKey, (Vec<Money>, u64)>, // key -> (values, transaction_count)
+}
+
+impl GroupAccumulator {
+    pub fn new(dimensions: &[Dimension]) -> Self {
+        GroupAccumulator { dimensions: dimensions.to_vec(), groups: HashMap::new() }
+    }
+
+    pub fn add(&mut self, tx: &Transaction) {
+        let key = group_key(tx, &self.dimensions);
+        let entry = self.groups.entry(key).or_insert((Vec::new(), 0));
+        entry.0.push(tx.value);
+        entry.1 += u64::from(tx.transaction_count);
+    }
+
+    // Add the groups of another accumulator (e.g. of another file), its values after ours
+    pub fn merge(&mut self, other: GroupAccumulator) {
+        for (key, (values, transaction_count)) in other.groups {
+            let entry = self.groups.entry(key).or_insert((Vec::new(), 0));
+            entry.0.extend(values);
+            entry.1 += transaction_count;
+        }
+    }
+
+    // Use mathematical computations to determine average, median, etc
+    pub fn finish(self) -> Vec<GroupStats> {
+        let mut result = Vec::new();
+        for (key, (values, transaction_count)) in self.groups {
+            let total: Money = values.iter().sum(); // Exact, no rounding
+            let count = values.len();
+            let average = if count > 0 { total.div_round(count as i128) } else { Money::ZERO };
+            let median = calculate_median(&values);
+
+            result.push(GroupStats { key, total, transaction_count, average, median, count });
+        }
+
+        result.sort_by(|a, b| a.key.cmp(&b.key)); // Alphabetical, periods chronological
+        result
+    }
+}
 
+// Group-by over a slice or any other iterator of transactions
+// e.g. group_by(&transactions, &[Dimension::Domain, Dimension::Location, Dimension::Period(Granularity::Quarter)])
+pub fn group_by<'a, I>(transactions: I, dimensions: &[Dimension]) -> Vec<GroupStats>
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    let mut acc = GroupAccumulator::new(dimensions);
     for tx in transactions {
-        // Group by location (as region)
-        map.entry(tx.location.clone())
-            .or_default()
-            .push(tx.value);
+        acc.add(tx);
+    }
+    acc.finish()
+}
+
+// Running accumulator for region statistics, a group-by on location
+pub struct RegionAccumulator {
+    groups: GroupAccumulator,
+}
+
+impl Default for RegionAccumulator {
+    fn default() -> Self {
+        RegionAccumulator { groups: GroupAccumulator::new(&[Dimension::Location]) }
     }
+}
+
+impl RegionAccumulator {
+    pub fn add(&mut self, tx: &Transaction) {
+        self.groups.add(tx);
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.groups.merge(other.groups);
+    }
+
+    pub fn finish(self) -> Vec<RegionStats> {
+        self.groups
+            .finish()
+            .into_iter()
+            .map(|stat| RegionStats {
+                region: stat.key.location.unwrap_or_default(),
+                total: stat.total,
+                average: stat.average,
+                median: stat.median,
+                count: stat.count,
+            })
+            .collect()
+    }
+}
+
+// Running accumulator for domain statistics, a group-by on domain
+pub struct DomainAccumulator {
+    groups: GroupAccumulator,
+}
 
-    let mut stats = Vec::new(); // Store RegionStats' components
+impl Default for DomainAccumulator {
+    fn default() -> Self {
+        DomainAccumulator { groups: GroupAccumulator::new(&[Dimension::Domain]) }
+    }
+}
 
-    for (location, values) in map {
-        let total: u64 = values.iter().sum();
-        let count = values.len();
-        let average = total as f64 / count as f64;
-        let median = calculate_median(&values);
+impl DomainAccumulator {
+    pub fn add(&mut self, tx: &Transaction) {
+        self.groups.add(tx);
+    }
 
-        stats.push(RegionStats {region: location, total, average, median, count});
+    pub fn merge(&mut self, other: Self) {
+        self.groups.merge(other.groups);
     }
 
-    stats.sort_by(|a, b| a.region.cmp(&b.region));
-    stats
+    pub fn finish(self) -> Vec<DomainStats> {
+        self.groups
+            .finish()
+            .into_iter()
+            .map(|stat| DomainStats {
+                domain: stat.key.domain.unwrap_or_default(),
+                total: stat.total,
+                average: stat.average,
+                median: stat.median,
+                count: stat.count,
+            })
+            .collect()
+    }
 }
 
 // Calculate median (for the aggregation functions)
-fn calculate_median(values: &[u64]) -> f64 {
+fn calculate_median(values: &[Money]) -> Money {
+    if values.is_empty() {
+        return Money::ZERO;
+    }
+
     // Sort the values
     let mut sorted = values.to_vec();
-    sorted.sort_unstable(); // for u64 type
+    sorted.sort_unstable();
 
     let n = sorted.len();
     let mid = n / 2;
 
-    if n % 2 == 0 {
-        return (sorted[mid - 1] + sorted[mid]) as f64 / 2.0;
+    if n.is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]).div_round(2)
     } else {
-        return sorted[mid] as f64;
+        sorted[mid]
     }
 }
 
-// Aggregate transaction values & counts by month
-// Loops over the transactions struct and push them into the hashmap for analysis
-// Use mathematical computations to determine average, median, etc
-pub fn aggregate_by_month(transactions: &[Transaction]) -> Vec<MonthStats> {
-    let mut agg_map: HashMap<u32, (Vec<u64>, u32)> = HashMap::new(); // month -> (values, transaction_count)
+// Running accumulator for the time aggregation, a group-by on the period
+// The granularity decides the bucket size (day, ISO week, month, quarter or year)
+pub struct PeriodAccumulator {
+    groups: GroupAccumulator,
+}
 
-    for tx in transactions {
-        let month = tx.date.month();
-        let entry = agg_map.entry(month).or_insert((Vec::new(), 0));
-        entry.0.push(tx.value);
-        entry.1 += tx.transaction_count;
+impl PeriodAccumulator {
+    pub fn new(granularity: Granularity) -> Self {
+        PeriodAccumulator { groups: GroupAccumulator::new(&[Dimension::Period(granularity)]) }
     }
 
-    let mut result = Vec::new();
-    for (month, (values, transaction_count)) in agg_map {
-        let total: u64 = values.iter().sum();
-        let count = values.len();
-        let average = if count > 0 { total as f64 / count as f64 } else { 0.0 };
-        let median = calculate_median(&values);
+    pub fn add(&mut self, tx: &Transaction) {
+        self.groups.add(tx);
+    }
 
-        result.push(MonthStats {
-            month,
-            value: total,
-            transaction_count,
-            average,
-            median,
-            count,
-        });
+    pub fn merge(&mut self, other: Self) {
+        self.groups.merge(other.groups);
     }
 
-    result.sort_by_key(|m| m.month);
-    result
+    pub fn finish(self) -> Vec<PeriodStats> {
+        self.groups
+            .finish()
+            .into_iter()
+            .filter_map(|stat| {
+                Some(PeriodStats {
+                    period: stat.key.period?,
+                    value: stat.total,
+                    transaction_count: stat.transaction_count,
+                    average: stat.average,
+                    median: stat.median,
+                    count: stat.count,
+                })
+            })
+            .collect() // Already chronological, across years
+    }
 }
 
-// Custom percentile function for the detect anomaly functions
-fn percentile(values: Vec<u64>, p: f64) -> f64 {
-    let mut sorted = values;
-    sorted.sort_unstable(); // Sort in-place
-
-    let position = (p * (sorted.len() - 1) as f64).round() as usize;
-    sorted[position] as f64
-}
+#[test]
+fn test_median() {
+    let values: Vec<Money> = [12, 7, 22, 15, 9, 30, 18, 5, 14, 10].into_iter().map(Money::from).collect();
+    let target_median = Money::from(13);
+    let func_num = calculate_median(&values);
 
-// Detect anomaly - return the vector rows of the anomaly (for value & transaction count)
-pub fn detect_anomaly_for_value(transactions: &[Transaction]) -> Vec<Transaction> {
-    let values: Vec<u64> = transactions.iter().map(|tx| tx.value).collect(); // [365554, 584958, 885720, ...]
+    assert_eq!(target_median, func_num);
 
-    // IQR (Q3 - Q1)
-    let q3 = percentile(values.clone(), 0.75);
-    let q1 = percentile(values, 0.25);
-    let iqr = q3 - q1;
+    // Refunds and cents: -2.50, 0.25, 1.00, 3.10 -> halfway between 0.25 and 1.00
+    let values: Vec<Money> = ["-2.50", "0.25", "1.00", "3.10"].iter().map(|v| v.parse().unwrap()).collect();
+    assert_eq!(calculate_median(&values).to_string(), "0.625");
+}
 
-    // Find the lower & upper bound
-    let lower = q1 - 1.5 * iqr;
-    let upper = q3 + 1.5 * iqr;
+#[test]
+// Same month in different years must land in different buckets, in date order
+fn test_month_buckets_keep_year() {
+    let rows = [((2023, 1, 5), "300"), ((2022, 1, 5), "100"), ((2022, 12, 5), "200")];
 
-    let outliers = transactions.iter().filter(|tx| (tx.value as f64) < lower || (tx.value as f64) > upper).cloned().collect(); // Cloned for mapping clone to each value
+    let mut acc = PeriodAccumulator::new(Granularity::Month);
+    for (date, value) in rows {
+        acc.add(&Transaction::test_row(date, "RETAIL", "Goa", value));
+    }
+    let stats = acc.finish();
 
-    outliers
+    let labels: Vec<String> = stats.iter().map(|m| m.period.to_string()).collect();
+    assert_eq!(labels, vec!["2022-01", "2022-12", "2023-01"]);
+    assert_eq!(stats[0].value, Money::from(100));
+    assert_eq!(stats[2].value, Money::from(300));
 }
 
-// Detect anomaly for transaction count instead of value
-pub fn detect_anomaly_for_transaction_count(transactions: &[Transaction]) -> Vec<Transaction> {
-    let transaction_counts: Vec<u64> = transactions.iter().map(|tx| tx.transaction_count as u64).collect();
-
-    let q3 = percentile(transaction_counts.clone(), 0.75); 
-    let q1 = percentile(transaction_counts, 0.25);
-    let iqr = q3 - q1;
+#[test]
+// Check the bucket start and label for every granularity
+fn test_period_granularities() {
+    use chrono::NaiveDate;
 
-    let lower = q1 - 1.5 * iqr;
-    let upper = q3 + 1.5 * iqr;
+    let date = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(); // A Sunday, still in ISO week 52 of 2022
 
-    let outliers = transactions.iter().filter(|tx| (tx.transaction_count as f64) < lower || (tx.transaction_count as f64) > upper).cloned().collect();
+    let labels: Vec<String> = [Granularity::Day, Granularity::Week, Granularity::Month, Granularity::Quarter, Granularity::Year]
+        .into_iter()
+        .map(|g| Period::containing(date, g).to_string())
+        .collect();
+    assert_eq!(labels, vec!["2023-01-01", "2022-W52", "2023-01", "2023-Q1", "2023"]);
 
-    outliers
+    let week = Period::containing(date, Granularity::Week);
+    assert_eq!(week.start, NaiveDate::from_ymd_opt(2022, 12, 26).unwrap());
 }
 
 #[test]
-// Test percentile & median function
-fn test_percentile() {
-    let values = vec![12, 7, 22, 15, 9, 30, 18, 5, 14, 10];
-    let target_num = 9.0; // 25th percentile (rounded)
-    let func_num = percentile(values, 0.25);
+// Group on domain x location x quarter and pick out one cell
+fn test_group_by_multiple_dimensions() {
+    let rows = [
+        ("RESTAURANT", "Goa", (2022, 1, 10), "100"),
+        ("RESTAURANT", "Goa", (2022, 3, 20), "300"),
+        ("RESTAURANT", "Goa", (2022, 4, 1), "50"),
+        ("RESTAURANT", "Bhuj", (2022, 1, 10), "70"),
+        ("RETAIL", "Goa", (2022, 1, 10), "90"),
+    ];
+    let transactions: Vec<Transaction> =
+        rows.iter().map(|(domain, location, date, value)| Transaction::test_row(*date, domain, location, value)).collect();
 
-    assert_eq!(target_num, func_num);
+    let stats = group_by(&transactions, &[Dimension::Domain, Dimension::Location, Dimension::Period(Granularity::Quarter)]);
+    assert_eq!(stats.len(), 4);
+
+    let goa_q1 = stats.iter().find(|s| s.key.to_string() == "RESTAURANT / Goa / 2022-Q1").unwrap();
+    assert_eq!(goa_q1.total, Money::from(400));
+    assert_eq!(goa_q1.count, 2);
+    assert_eq!(goa_q1.median, Money::from(200));
+
+    // Split over two accumulators (two files read in parallel) and merged: same table
+    let dimensions = [Dimension::Domain, Dimension::Location, Dimension::Period(Granularity::Quarter)];
+    let mut first = GroupAccumulator::new(&dimensions);
+    let mut second = GroupAccumulator::new(&dimensions);
+    transactions[..2].iter().for_each(|tx| first.add(tx));
+    transactions[2..].iter().for_each(|tx| second.add(tx));
+    first.merge(second);
+    assert_eq!(format!("{:?}", first.finish()), format!("{:?}", stats));
 }
 
 #[test]
-fn test_median() {
-    let values = vec![12, 7, 22, 15, 9, 30, 18, 5, 14, 10];
-    let target_median = 13.0; 
-    let func_num = calculate_median(&values);
-
-    assert_eq!(target_median, func_num);
-}
\ No newline at end of file
+// A year of transaction counts doesn't fit in a u32
+fn test_group_transaction_count_total() {
+    let row = |transaction_count| Transaction { transaction_count, ..Transaction::test_row((2022, 1, 1), "RETAIL", "Goa", "1") };
+    let stats = group_by(&[row(4_000_000_000), row(400_000_000)], &[Dimension::Period(Granularity::Year)]);
+    assert_eq!(stats[0].transaction_count, 4_400_000_000);
+}
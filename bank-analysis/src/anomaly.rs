@@ -0,0 +1,301 @@
+use crate::analysis::group_key;
+use crate::models::{Dimension, GroupKey, Metric, Transaction};
+use std::collections::HashMap;
+
+// Lower & upper bound produced by a detector, anything outside is an anomaly
+#[derive(Debug, Clone, Copy)]
+pub struct Fence {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Fence {
+    // Nothing to compare against, so nothing can be an outlier
+    pub fn open() -> Self {
+        Fence { lower: f64::NEG_INFINITY, upper: f64::INFINITY }
+    }
+
+    pub fn is_outlier(&self, value: f64) -> bool {
+        value < self.lower || value > self.upper
+    }
+}
+
+// Which side of the fence the value crossed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Below,
+    Above,
+}
+
+// One flagged row, with everything needed to rank, filter and explain it
+#[derive(Debug, Clone)]
+pub struct Anomaly {
+    pub index: usize, // Position of the row in the analyzed stream (0-based)
+    pub metric: Metric,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub score: f64, // Distance past the fence relative to the fence width, 0 = right on the fence
+    pub direction: Direction,
+    pub group: GroupKey, // Group whose baseline was violated (empty key = global)
+}
+
+// Severity of a value outside the fence
+// One-sided fences (fixed limits) have no width, the crossed bound itself is used as the scale
+fn severity(value: f64, fence: &Fence) -> f64 {
+    let (excess, bound) = if value > fence.upper {
+        (value - fence.upper, fence.upper)
+    } else {
+        (fence.lower - value, fence.lower)
+    };
+
+    let width = fence.upper - fence.lower;
+    let scale = if width.is_finite() && width > 0.0 { width } else { bound.abs().max(1.0) };
+    excess / scale
+}
+
+// A method turning the values of one metric (first pass) into the fence used to flag rows (second pass)
+pub trait AnomalyDetector {
+    fn fit(&self, values: &[f64]) -> Fence;
+}
+
+// Interquartile range rule: [Q1 - k * IQR, Q3 + k * IQR], k = 1.5 is the classic Tukey fence
+pub struct Iqr {
+    pub k: f64,
+}
+
+impl AnomalyDetector for Iqr {
+    fn fit(&self, values: &[f64]) -> Fence {
+        if values.is_empty() {
+            return Fence::open();
+        }
+
+        // IQR (Q3 - Q1)
+        let q3 = percentile(values.to_vec(), 0.75);
+        let q1 = percentile(values.to_vec(), 0.25);
+        let iqr = q3 - q1;
+
+        // Find the lower & upper bound
+        Fence { lower: q1 - self.k * iqr, upper: q3 + self.k * iqr }
+    }
+}
+
+// Standard score: flag values more than `threshold` standard deviations away from the mean
+pub struct ZScore {
+    pub threshold: f64,
+}
+
+impl AnomalyDetector for ZScore {
+    fn fit(&self, values: &[f64]) -> Fence {
+        if values.is_empty() {
+            return Fence::open();
+        }
+
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        let std_dev = variance.sqrt();
+
+        Fence { lower: mean - self.threshold * std_dev, upper: mean + self.threshold * std_dev }
+    }
+}
+
+// Modified z-score (Iglewicz & Hoaglin): median and median absolute deviation instead of mean and std
+// Less sensitive to the outliers themselves, 3.5 is the usual threshold
+pub struct ModifiedZScore {
+    pub threshold: f64,
+}
+
+impl AnomalyDetector for ModifiedZScore {
+    fn fit(&self, values: &[f64]) -> Fence {
+        if values.is_empty() {
+            return Fence::open();
+        }
+
+        let median = percentile(values.to_vec(), 0.5);
+        let deviations: Vec<f64> = values.iter().map(|v| (v - median).abs()).collect();
+        let mad = percentile(deviations.clone(), 0.5);
+
+        // Modified z = 0.6745 * (x - median) / MAD, solved for x at +/- threshold
+        // More than half the values equal to the median gives MAD = 0, fall back to the mean absolute deviation
+        let width = if mad > 0.0 {
+            self.threshold * mad / 0.6745
+        } else {
+            let mean_ad = deviations.iter().sum::<f64>() / deviations.len() as f64;
+            self.threshold * mean_ad / 0.7979
+        };
+        Fence { lower: median - width, upper: median + width }
+    }
+}
+
+// Fixed business limits, e.g. anything above 10 million; a missing side is unbounded
+pub struct FixedThreshold {
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+}
+
+impl AnomalyDetector for FixedThreshold {
+    fn fit(&self, _values: &[f64]) -> Fence {
+        Fence {
+            lower: self.lower.unwrap_or(f64::NEG_INFINITY),
+            upper: self.upper.unwrap_or(f64::INFINITY),
+        }
+    }
+}
+
+// Custom percentile function for the detectors
+fn percentile(values: Vec<f64>, p: f64) -> f64 {
+    let mut sorted = values;
+    sorted.sort_unstable_by(|a, b| a.total_cmp(b)); // Sort in-place
+
+    let position = (p * (sorted.len() - 1) as f64).round() as usize;
+    sorted[position]
+}
+
+// Collects one metric per row (first pass), separately inside every group (city, domain, period or combinations)
+// Only the numbers are kept, the rows themselves can be streamed again for the second pass
+// No dimensions means a single global group
+pub struct GroupFenceAccumulator {
+    metric: Metric,
+    dimensions: Vec<Dimension>,
+    groups: HashMap<GroupKey, Vec<f64>>,
+}
+
+impl GroupFenceAccumulator {
+    pub fn new(metric: Metric, dimensions: &[Dimension]) -> Self {
+        GroupFenceAccumulator { metric, dimensions: dimensions.to_vec(), groups: HashMap::new() }
+    }
+
+    pub fn add(&mut self, tx: &Transaction) {
+        let key = group_key(tx, &self.dimensions);
+        self.groups.entry(key).or_default().push(self.metric.of(tx));
+    }
+
+    // Add the values of another accumulator (e.g. of another file) after ours, so the fit doesn't depend on the merge
+    pub fn merge(&mut self, other: GroupFenceAccumulator) {
+        for (key, values) in other.groups {
+            self.groups.entry(key).or_default().extend(values);
+        }
+    }
+
+    // Fit the detector inside every group
+    pub fn finish(self, detector: &dyn AnomalyDetector) -> GroupFences {
+        let fences = self.groups.into_iter().map(|(key, values)| (key, detector.fit(&values))).collect();
+        GroupFences { metric: self.metric, dimensions: self.dimensions, fences }
+    }
+}
+
+// The fence of every group, used in the second pass
+// A row is only compared against the rows of its own group
+pub struct GroupFences {
+    metric: Metric,
+    dimensions: Vec<Dimension>,
+    fences: HashMap<GroupKey, Fence>,
+}
+
+impl GroupFences {
+    // Check the row at `index` against its group's fence, None if the value is normal
+    pub fn check(&self, index: usize, tx: &Transaction) -> Option<Anomaly> {
+        let group = group_key(tx, &self.dimensions);
+        let fence = self.fences.get(&group)?; // Group never seen in the first pass
+        let value = self.metric.of(tx);
+        if !fence.is_outlier(value) {
+            return None;
+        }
+
+        Some(Anomaly {
+            index,
+            metric: self.metric,
+            value,
+            lower: fence.lower,
+            upper: fence.upper,
+            score: severity(value, fence),
+            direction: if value > fence.upper { Direction::Above } else { Direction::Below },
+            group,
+        })
+    }
+}
+
+#[test]
+// Test percentile function
+fn test_percentile() {
+    let values = vec![12.0, 7.0, 22.0, 15.0, 9.0, 30.0, 18.0, 5.0, 14.0, 10.0];
+    let target_num = 9.0; // 25th percentile (rounded)
+    let func_num = percentile(values, 0.25);
+
+    assert_eq!(target_num, func_num);
+}
+
+#[test]
+// Every method must flag the obvious outlier and keep the regular values
+fn test_detectors() {
+    let values = vec![10.0, 12.0, 11.0, 9.0, 10.0, 11.0, 12.0, 10.0, 9.0, 11.0, 100.0];
+
+    let detectors: Vec<Box<dyn AnomalyDetector>> = vec![
+        Box::new(Iqr { k: 1.5 }),
+        Box::new(ZScore { threshold: 3.0 }),
+        Box::new(ModifiedZScore { threshold: 3.5 }),
+        Box::new(FixedThreshold { lower: None, upper: Some(50.0) }),
+    ];
+    for detector in detectors {
+        let fence = detector.fit(&values);
+        assert!(fence.is_outlier(100.0));
+        assert!(!fence.is_outlier(10.0));
+        assert!(!fence.is_outlier(12.0));
+    }
+
+    // A larger k widens the IQR fence
+    let narrow = Iqr { k: 1.5 }.fit(&values);
+    let wide = Iqr { k: 3.0 }.fit(&values);
+    assert!(wide.upper > narrow.upper);
+}
+
+#[test]
+// A value that is normal in a big city is an outlier in a small one
+fn test_fences_per_group() {
+    let row = |location: &str, value: i64| Transaction::test_row((2022, 1, 1), "RETAIL", location, &value.to_string());
+
+    let mut transactions = Vec::new();
+    for value in [900, 1000, 1100, 950, 1050] {
+        transactions.push(row("Mumbai", value));
+    }
+    for value in [10, 12, 11, 9, 1000] {
+        transactions.push(row("Bhuj", value));
+    }
+
+    let mut global = GroupFenceAccumulator::new(Metric::Value, &[]);
+    let mut by_city = GroupFenceAccumulator::new(Metric::Value, &[Dimension::Location]);
+    for tx in &transactions {
+        global.add(tx);
+        by_city.add(tx);
+    }
+    let iqr = Iqr { k: 1.5 };
+    let global = global.finish(&iqr);
+    let by_city = by_city.finish(&iqr);
+
+    // Globally 1000 looks fine, within Bhuj it is far outside the fence
+    let bhuj_outlier = &transactions[9];
+    assert!(global.check(9, bhuj_outlier).is_none());
+    let anomaly = by_city.check(9, bhuj_outlier).unwrap();
+    assert_eq!(anomaly.index, 9);
+    assert_eq!(anomaly.group.location.as_deref(), Some("Bhuj"));
+    assert_eq!(anomaly.direction, Direction::Above);
+    assert_eq!(anomaly.value, 1000.0);
+    assert!(anomaly.upper < 1000.0);
+    assert!(anomaly.score > 1.0);
+
+    // The same value in Mumbai is normal
+    assert!(by_city.check(1, &transactions[1]).is_none());
+}
+
+#[test]
+// Score grows with the distance past the fence
+fn test_severity() {
+    let fence = Fence { lower: 0.0, upper: 10.0 };
+    assert_eq!(severity(15.0, &fence), 0.5);
+    assert_eq!(severity(-20.0, &fence), 2.0);
+
+    // One-sided limit: relative to the bound
+    let fixed = Fence { lower: f64::NEG_INFINITY, upper: 100.0 };
+    assert_eq!(severity(150.0, &fixed), 0.5);
+}
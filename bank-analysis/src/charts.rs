@@ -0,0 +1,278 @@
+// Charming for visualization
+use charming::{
+    component::{Axis, Title},
+    element::{AxisLabel, AxisType, NameLocation, Tooltip, ItemStyle},
+    series::{Bar, Scatter, Line},
+    Chart,
+};
+use serde::Deserialize;
+
+use bank_analysis::models::{RegionStats, DomainStats, PeriodStats, Granularity};
+use crate::config::PanelConfig;
+
+// Kind of series drawn by a configured panel
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChartType {
+    #[default]
+    Scatter,
+    Line,
+    Bar,
+}
+
+// How the y axis range is derived from the plotted values
+#[derive(Debug, Clone, Copy)]
+pub struct AxisScale {
+    pub padding: f64,    // Extra room above/below the data, as a fraction of the data range
+    pub from_zero: bool, // Always include 0 in the axis
+}
+
+impl Default for AxisScale {
+    fn default() -> Self {
+        AxisScale { padding: 0.1, from_zero: false }
+    }
+}
+
+impl AxisScale {
+    // Compute the (min, max) of the axis from the actual data, rounded to readable tick values
+    pub fn bounds(&self, values: &[f64]) -> (f64, f64) {
+        let finite: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+        if finite.is_empty() {
+            return (0.0, 1.0); // Nothing to plot, any range will do
+        }
+
+        let low = finite.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = finite.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        // A single value (or all equal) still needs some height around it, even without padding
+        let (span, padding) = if high > low { (high - low, self.padding) } else { (high.abs().max(1.0), self.padding.max(0.5)) };
+        let mut min = low - span * padding;
+        let mut max = high + span * padding;
+
+        if self.from_zero {
+            min = min.min(0.0);
+            max = max.max(0.0);
+        } else if low >= 0.0 && min < 0.0 {
+            min = 0.0; // Don't pad non-negative data below zero
+        }
+
+        // Snap outwards to a round step so the tick labels stay readable
+        let step = nice_step(max - min);
+        ((min / step).floor() * step, (max / step).ceil() * step)
+    }
+}
+
+// Round a fifth of the range to 1, 2 or 5 times a power of ten
+fn nice_step(range: f64) -> f64 {
+    let raw = range / 5.0;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let normalized = raw / magnitude;
+
+    let nice = if normalized <= 1.0 {
+        1.0
+    } else if normalized <= 2.0 {
+        2.0
+    } else if normalized <= 5.0 {
+        5.0
+    } else {
+        10.0
+    };
+    nice * magnitude
+}
+
+// Value axis whose range fits the plotted values
+fn value_axis(name: &str, name_gap: i32, values: &[f64], scale: AxisScale) -> Axis {
+    let (min, max) = scale.bounds(values);
+    Axis::new()
+        .type_(AxisType::Value)
+        .name(name)
+        .name_location(NameLocation::Middle)
+        .name_gap(name_gap)
+        .min(min)
+        .max(max)
+}
+
+// Line chart of one statistic over the time periods
+fn period_chart(title: String, granularity: Granularity, labels: &[String], y_name: &str, name_gap: i32, values: Vec<f64>, scale: AxisScale) -> Chart {
+    Chart::new()
+        .title(Title::new().text(title).left("center"))
+        .tooltip(Tooltip::new())
+        .x_axis(
+            Axis::new()
+                .type_(AxisType::Category)
+                .name(granularity.label())
+                .data(labels.to_vec())
+                .axis_label(AxisLabel::new().rotate(45).interval(0))
+        )
+        .y_axis(value_axis(y_name, name_gap, &values, scale))
+        .series(Line::new().data(values))
+}
+
+pub fn create_time_series_graph(period_stats: &[PeriodStats], granularity: Granularity, scale: AxisScale) -> Vec<Chart> {
+    // Stats arrive sorted by period, so the labels are already chronological
+    let labels: Vec<String> = period_stats.iter().map(|stat| stat.period.to_string()).collect();
+
+    vec![
+        // Chart 1: Transaction value
+        period_chart(
+            format!("Transaction Value by {}", granularity.label()),
+            granularity,
+            &labels,
+            "Transaction Value",
+            105,
+            period_stats.iter().map(|stat| stat.value.to_f64()).collect(),
+            scale,
+        ),
+        // Chart 2: Transaction count
+        period_chart(
+            format!("Total Number of Transactions by {}", granularity.label()),
+            granularity,
+            &labels,
+            "Number of Transactions",
+            100,
+            period_stats.iter().map(|stat| stat.transaction_count as f64).collect(),
+            scale,
+        ),
+    ]
+}
+
+// Scatter chart of one statistic per category (city, domain, ...)
+fn category_chart(title: &str, category: &str, labels: &[String], y_axis: Axis, series: Scatter) -> Chart {
+    Chart::new()
+        .title(Title::new().text(title).left("center"))
+        .tooltip(Tooltip::new())
+        .x_axis(
+            Axis::new()
+                .type_(AxisType::Category)
+                .name(category)
+                .data(labels.to_vec())
+                .axis_label(AxisLabel::new().rotate(45).interval(0)),
+        )
+        .y_axis(y_axis)
+        .series(series)
+}
+
+// The four statistics (total, average, median, count) of every category
+// Shared by the city and the domain charts
+fn create_category_charts(category: &str, labels: &[String], totals: Vec<f64>, averages: Vec<f64>, medians: Vec<f64>, counts: Vec<f64>, scale: AxisScale) -> Vec<Chart> {
+    vec![
+        // Chart 1: Total
+        category_chart(
+            &format!("Total Transaction Value by {}", category),
+            category,
+            labels,
+            value_axis("Total Transaction Value", 100, &totals, scale),
+            Scatter::new()
+                .name("Transaction Value")
+                .data(totals)
+                .item_style(ItemStyle::new().color("orange")),
+        ),
+        // Chart 2: Average
+        category_chart(
+            &format!("Average Transaction Value by {}", category),
+            category,
+            labels,
+            value_axis("Average Transaction Value", 70, &averages, scale),
+            Scatter::new()
+                .name("Transaction Value")
+                .data(averages)
+                .item_style(ItemStyle::new().color("purple")),
+        ),
+        // Chart 3: Median
+        category_chart(
+            &format!("Median Transaction Value by {}", category),
+            category,
+            labels,
+            value_axis("Median Transaction Value", 70, &medians, scale),
+            Scatter::new()
+                .name("Transaction Value")
+                .data(medians)
+                .item_style(ItemStyle::new().color("green")),
+        ),
+        // Chart 4: Count
+        category_chart(
+            &format!("Total Number of Transactions by {}", category),
+            category,
+            labels,
+            value_axis("Total Transactions", 60, &counts, scale),
+            Scatter::new()
+                .name("Transactions")
+                .data(counts)
+                .item_style(ItemStyle::new().color("red")),
+        ),
+    ]
+}
+
+pub fn create_charts(region_stats: &[RegionStats], scale: AxisScale) -> Vec<Chart> {
+    create_category_charts(
+        "City",
+        &region_stats.iter().map(|stat| stat.region.clone()).collect::<Vec<_>>(),
+        region_stats.iter().map(|stat| stat.total.to_f64()).collect(),
+        region_stats.iter().map(|stat| stat.average.to_f64()).collect(),
+        region_stats.iter().map(|stat| stat.median.to_f64()).collect(),
+        region_stats.iter().map(|stat| stat.count as f64).collect(),
+        scale,
+    )
+}
+
+// Same four statistics as the city charts, split by business domain instead
+pub fn create_domain_charts(domain_stats: &[DomainStats], scale: AxisScale) -> Vec<Chart> {
+    create_category_charts(
+        "Domain",
+        &domain_stats.iter().map(|stat| stat.domain.clone()).collect::<Vec<_>>(),
+        domain_stats.iter().map(|stat| stat.total.to_f64()).collect(),
+        domain_stats.iter().map(|stat| stat.average.to_f64()).collect(),
+        domain_stats.iter().map(|stat| stat.median.to_f64()).collect(),
+        domain_stats.iter().map(|stat| stat.count as f64).collect(),
+        scale,
+    )
+}
+
+// Chart of one configured panel, one point / bar per group
+pub fn panel_chart(panel: &PanelConfig, x_name: &str, labels: &[String], values: Vec<f64>, scale: AxisScale) -> Chart {
+    let y_name = panel.y_name.as_deref().unwrap_or(panel.stat.label());
+    let y_axis = value_axis(y_name, panel.name_gap.unwrap_or(80), &values, scale);
+    let style = match &panel.color {
+        Some(color) => ItemStyle::new().color(color.as_str()),
+        None => ItemStyle::new(),
+    };
+
+    let chart = Chart::new()
+        .title(Title::new().text(panel.title.as_str()).left("center"))
+        .tooltip(Tooltip::new())
+        .x_axis(
+            Axis::new()
+                .type_(AxisType::Category)
+                .name(x_name)
+                .data(labels.to_vec())
+                .axis_label(AxisLabel::new().rotate(45).interval(0)),
+        )
+        .y_axis(y_axis);
+
+    match panel.chart_type {
+        ChartType::Scatter => chart.series(Scatter::new().name(y_name).data(values).item_style(style)),
+        ChartType::Line => chart.series(Line::new().name(y_name).data(values).item_style(style)),
+        ChartType::Bar => chart.series(Bar::new().name(y_name).data(values).item_style(style)),
+    }
+}
+
+#[test]
+fn test_axis_bounds() {
+    // Values around 16 billion, like the city totals
+    let values = vec![15_800_000_000.0, 16_400_000_000.0, 17_100_000_000.0];
+    let (min, max) = AxisScale::default().bounds(&values);
+    assert!((15_000_000_001.0..=15_800_000_000.0).contains(&min));
+    assert!((17_100_000_000.0..18_000_000_000.0).contains(&max));
+
+    // Starting at zero keeps the upper bound but drops the lower one to 0
+    let (min, max) = AxisScale { padding: 0.0, from_zero: true }.bounds(&values);
+    assert_eq!(min, 0.0);
+    assert!(max >= 17_100_000_000.0);
+
+    // No data still gives a usable range
+    assert_eq!(AxisScale::default().bounds(&[]), (0.0, 1.0));
+
+    // A single city without padding: some room around its value
+    let (min, max) = AxisScale { padding: 0.0, from_zero: false }.bounds(&[250.0]);
+    assert!(min < 250.0 && max > 250.0);
+}
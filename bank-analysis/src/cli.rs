@@ -0,0 +1,341 @@
+use chrono::NaiveDate;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use std::error::Error;
+
+use bank_analysis::currency::{CurrencyConverter, ExchangeRates};
+use bank_analysis::export::ExportFormat;
+use bank_analysis::input::InputReader;
+use bank_analysis::normalize::{Dictionary, Normalizer};
+use bank_analysis::parser::{InputFormat, Schema};
+use bank_analysis::anomaly::{AnomalyDetector, FixedThreshold, Iqr, ModifiedZScore, ZScore};
+use bank_analysis::models::{Dimension, Granularity, Metric};
+use crate::charts::AxisScale;
+
+// Defaults used when no subcommand is given (same behavior as the original binary)
+pub const DEFAULT_INPUT: &str = "../data/bankdataset.csv";
+pub const DEFAULT_OUTPUT: &str = "stats.html";
+
+// Command line for the bank transaction analysis
+#[derive(Parser, Debug)]
+#[command(version, about = "Bank transaction statistics, charts and anomaly detection")]
+pub struct Cli {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    // Without a subcommand: build the report, then print the anomalies
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Build the HTML dashboard
+    Report(ReportArgs),
+    /// Print the rows flagged as anomalies
+    Anomalies(AnomalyArgs),
+    /// Print aggregated statistics as a table
+    Stats(StatsArgs),
+}
+
+// Which rows to read, shared by every subcommand
+#[derive(Args, Debug, Clone)]
+pub struct InputArgs {
+    /// Input CSV, Parquet, Arrow or JSON file, directory or glob pattern (e.g. "feeds/*/2022-*.csv.gz"), "-" for stdin;
+    /// repeat -i for several, they are read one after the other. Commands reading the rows more than once (anomalies
+    /// and the default run) keep the rows of stdin in memory, ~80 bytes per row
+    #[arg(short, long = "input", global = true, action = ArgAction::Append, default_value = DEFAULT_INPUT)]
+    pub inputs: Vec<String>,
+
+    /// Format of the input files: csv, parquet, arrow, arrows or json (default: from the content and extension)
+    #[arg(long, global = true)]
+    pub format: Option<InputFormat>,
+
+    /// Number of input files parsed in parallel
+    #[arg(short, long, global = true, default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Column mapping and date formats of the input files (.toml, .yaml)
+    #[arg(long, global = true)]
+    pub schema: Option<String>,
+
+    /// Date format of the input files in chrono syntax (e.g. %d/%m/%Y), preferred to the schema's; turns off the auto-detection
+    #[arg(long, global = true)]
+    pub date_format: Vec<String>,
+
+    /// Only keep transactions on or after this date (YYYY-MM-DD)
+    #[arg(long, global = true)]
+    pub from: Option<NaiveDate>,
+
+    /// Only keep transactions on or before this date (YYYY-MM-DD)
+    #[arg(long, global = true)]
+    pub to: Option<NaiveDate>,
+
+    /// Dictionary of domain and location spellings to rewrite (.toml, .yaml), see normalize.rs
+    #[arg(long, global = true)]
+    pub normalize: Option<String>,
+
+    /// Write the list of rewritten raw values (field, raw, normalized, match, rows) to this file
+    #[arg(long, global = true)]
+    pub normalization_report: Option<String>,
+
+    /// Skip malformed rows instead of stopping at the first one
+    #[arg(long, global = true)]
+    pub lenient: bool,
+
+    /// Write the skipped rows (file, line, reason, raw record) to this CSV file
+    #[arg(long, global = true, requires = "lenient")]
+    pub quarantine: Option<String>,
+
+    /// Fail anyway when more than this fraction of the rows is malformed
+    #[arg(long, global = true, default_value_t = 0.05, value_parser = parse_error_rate)]
+    pub max_error_rate: f64,
+
+    /// Reporting currency (e.g. INR), rows in another currency are converted with --rates
+    #[arg(long, global = true)]
+    pub currency: Option<String>,
+
+    /// Exchange-rate CSV with date,currency,rate columns (value of 1 unit in the reporting currency)
+    #[arg(long, global = true, requires = "currency")]
+    pub rates: Option<String>,
+
+}
+
+fn parse_error_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("'{}' is not an error rate (a fraction from 0 to 1, e.g. 0.05)", s)),
+    }
+}
+
+impl InputArgs {
+    // Load the schema, dictionary and rates once, for every pass of the run
+    // keep_stdin for the commands going over the rows more than once
+    pub fn reader(&self, keep_stdin: bool) -> Result<InputReader, Box<dyn Error>> {
+        let mut reader = InputReader::new(&self.inputs);
+        reader.format = self.format;
+        reader.jobs = self.jobs;
+        reader.schema = self.schema()?;
+        reader.from = self.from;
+        reader.to = self.to;
+        reader.normalizer = self.normalizer()?;
+        reader.converter = self.converter()?;
+        reader.lenient = self.lenient;
+        reader.max_error_rate = self.max_error_rate;
+        reader.keep_stdin = keep_stdin;
+        Ok(reader)
+    }
+
+    // Schema file (if any) with the --date-format formats first
+    pub fn schema(&self) -> Result<Schema, Box<dyn Error>> {
+        let mut schema = match &self.schema {
+            Some(path) => Schema::load(path)?,
+            None => Schema::default(),
+        };
+        schema.date_formats.splice(0..0, self.date_format.iter().cloned());
+        Ok(schema)
+    }
+
+    // Built-in spelling fixes, plus the --normalize dictionary
+    pub fn normalizer(&self) -> Result<Normalizer, Box<dyn Error>> {
+        Ok(match &self.normalize {
+            Some(path) => Normalizer::new(&Dictionary::load(path)?),
+            None => Normalizer::default(),
+        })
+    }
+
+    // Converter to the reporting currency, None keeps the values as they are in the files
+    // Without --rates only rows already in the reporting currency (or without one) are accepted
+    pub fn converter(&self) -> Result<Option<CurrencyConverter>, Box<dyn Error>> {
+        let Some(currency) = &self.currency else {
+            return Ok(None);
+        };
+        let rates = match &self.rates {
+            Some(path) => ExchangeRates::load(path)?,
+            None => ExchangeRates::default(),
+        };
+        Ok(Some(CurrencyConverter::new(currency, rates)))
+    }
+}
+
+// Groups of charts that can be put on the dashboard
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChartGroup {
+    Region,
+    Domain,
+    Time,
+}
+
+#[derive(Args, Debug)]
+pub struct ReportArgs {
+    /// Where to write the HTML dashboard
+    #[arg(short, long, default_value = DEFAULT_OUTPUT)]
+    pub output: String,
+
+    /// Chart groups to render (default: all)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub charts: Vec<ChartGroup>,
+
+    /// Bucket size of the time series (day, week, month, quarter, year)
+    #[arg(short, long, default_value = "month")]
+    pub granularity: Granularity,
+
+    /// Dashboard config file (.toml, .yaml) declaring the sections and panels, replaces the built-in charts
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Charts side by side in every dashboard section
+    #[arg(long, default_value_t = 1)]
+    pub columns: usize,
+
+    /// Height of every chart in pixels
+    #[arg(long, default_value_t = 800)]
+    pub chart_height: u32,
+
+    /// Extra room around the data on the y axes, as a fraction of the data range
+    #[arg(long, default_value_t = AxisScale::default().padding, value_parser = parse_padding)]
+    pub axis_padding: f64,
+
+    /// Start every y axis at zero
+    #[arg(long)]
+    pub axis_from_zero: bool,
+
+    /// Also write the region, domain and period stats tables next to the HTML (csv, json, jsonl, parquet)
+    #[arg(long, value_delimiter = ',')]
+    pub export_stats: Vec<ExportFormat>,
+
+    /// Embed the ECharts library in the HTML file so it renders without network access
+    #[arg(long)]
+    pub offline: bool,
+}
+
+impl Default for ReportArgs {
+    fn default() -> Self {
+        ReportArgs {
+            output: DEFAULT_OUTPUT.to_string(),
+            charts: Vec::new(),
+            granularity: Granularity::Month,
+            config: None,
+            columns: 1,
+            chart_height: 800,
+            axis_padding: AxisScale::default().padding,
+            axis_from_zero: false,
+            export_stats: Vec::new(),
+            offline: false,
+        }
+    }
+}
+
+// --axis-padding: a fraction, zero or more
+fn parse_padding(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(padding) if padding >= 0.0 && padding.is_finite() => Ok(padding),
+        _ => Err(format!("'{}' is not a padding fraction (0 or more, e.g. 0.1)", s)),
+    }
+}
+
+impl ReportArgs {
+    pub fn axis_scale(&self) -> AxisScale {
+        AxisScale { padding: self.axis_padding, from_zero: self.axis_from_zero }
+    }
+
+    // An empty selection means every chart group
+    pub fn wants(&self, group: ChartGroup) -> bool {
+        self.charts.is_empty() || self.charts.contains(&group)
+    }
+}
+
+// Anomaly detection methods, see anomaly.rs
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Iqr,
+    Zscore,
+    Mad,
+    Fixed,
+}
+
+#[derive(Args, Debug)]
+pub struct AnomalyArgs {
+    /// Metrics to check: value, count, average (default: value,count)
+    #[arg(long, value_delimiter = ',', default_value = "value,count")]
+    pub metric: Vec<Metric>,
+
+    /// Compute the fences within each group, e.g. location or domain,month (default: one global fence)
+    #[arg(long, value_delimiter = ',')]
+    pub by: Vec<Dimension>,
+
+    /// Detection method
+    #[arg(long, value_enum, default_value = "iqr")]
+    pub method: Method,
+
+    /// IQR multiplier of the iqr method
+    #[arg(long, default_value_t = 1.5)]
+    pub k: f64,
+
+    /// Score above which a row is flagged (zscore default 3.0, mad default 3.5)
+    #[arg(long)]
+    pub threshold: Option<f64>,
+
+    /// Lower limit of the fixed method
+    #[arg(long)]
+    pub lower: Option<f64>,
+
+    /// Upper limit of the fixed method
+    #[arg(long)]
+    pub upper: Option<f64>,
+
+    /// Also write the anomalies to this file (.json, .jsonl, .csv or .parquet), can be repeated
+    #[arg(short, long)]
+    pub export: Vec<String>,
+
+    /// Format of the export files when the extension doesn't tell (json, jsonl, csv, parquet)
+    #[arg(long)]
+    pub export_format: Option<ExportFormat>,
+
+    /// Only print the number of anomalies, not every row
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+impl Default for AnomalyArgs {
+    fn default() -> Self {
+        AnomalyArgs {
+            metric: vec![Metric::Value, Metric::TransactionCount],
+            by: Vec::new(),
+            method: Method::Iqr,
+            k: 1.5,
+            threshold: None,
+            lower: None,
+            upper: None,
+            export: Vec::new(),
+            export_format: None,
+            quiet: false,
+        }
+    }
+}
+
+impl AnomalyArgs {
+    // Build the selected detector
+    pub fn detector(&self) -> Box<dyn AnomalyDetector> {
+        match self.method {
+            Method::Iqr => Box::new(Iqr { k: self.k }),
+            Method::Zscore => Box::new(ZScore { threshold: self.threshold.unwrap_or(3.0) }),
+            Method::Mad => Box::new(ModifiedZScore { threshold: self.threshold.unwrap_or(3.5) }),
+            Method::Fixed => Box::new(FixedThreshold { lower: self.lower, upper: self.upper }),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// Dimensions to group on, e.g. domain,location,quarter
+    #[arg(long, value_delimiter = ',', default_value = "location")]
+    pub by: Vec<Dimension>,
+
+    /// Also write the table to this file (.json, .jsonl, .csv or .parquet), can be repeated
+    #[arg(short, long)]
+    pub export: Vec<String>,
+
+    /// Format of the export files when the extension doesn't tell (json, jsonl, csv, parquet)
+    #[arg(long)]
+    pub export_format: Option<ExportFormat>,
+}
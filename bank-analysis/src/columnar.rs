@@ -0,0 +1,315 @@
+use arrow::array::{Array, ArrayRef, AsArray, Date32Array, RecordBatch, RecordBatchReader, StringArray};
+use arrow::compute::{can_cast_types, cast};
+use arrow::datatypes::{DataType, Float64Type};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::{FileReader, StreamReader};
+use bytes::Bytes;
+use csv::StringRecord;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use parquet::file::reader::ChunkReader;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::sync::Arc;
+
+use crate::models::Transaction;
+use crate::money::{Money, DECIMALS};
+use crate::parser::{open_input, DateParser, RecordSource, RowError, Schema, TransactionStream};
+
+// Transaction fields, in the order the columns are kept
+const FIELDS: [&str; 6] = ["date", "domain", "location", "value", "transaction_count", "currency"];
+
+type Batches = Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>> + Send>;
+
+// Position of every field in the file's columns, through the same mapping as the csv headers
+fn field_positions(reader: &dyn RecordBatchReader, schema: &Schema) -> Result<Vec<Option<usize>>, Box<dyn Error>> {
+    let arrow_schema = reader.schema();
+    let names: StringRecord = arrow_schema.fields().iter().map(|field| field.name().as_str()).collect();
+    let mapped = schema.map_headers(&names)?;
+
+    let positions: Vec<Option<usize>> = FIELDS.iter().map(|field| mapped.iter().position(|header| header == *field)).collect();
+    for (field, position) in FIELDS.iter().zip(&positions) {
+        if let Some(position) = position {
+            let column = arrow_schema.field(*position);
+            if !can_cast_types(column.data_type(), &DataType::Utf8) {
+                return Err(format!("column '{}' ({}) has unsupported type {}", column.name(), field, column.data_type()).into());
+            }
+        }
+    }
+    Ok(positions)
+}
+
+// One record batch, every mapped column as text
+// Date columns stored as dates/timestamps are also kept as dates, nothing to guess there
+struct Columns {
+    text: Vec<Option<StringArray>>, // In FIELDS order, None for a missing optional column
+    dates: Option<Date32Array>,
+    rows: usize,
+}
+
+// Column as text, float amounts rounded to the Money decimals first: a plain cast would keep the binary noise
+// (0.30000000000000004 has too many decimals) and print large values in exponent form
+fn column_text(column: &ArrayRef, field: &str) -> Result<StringArray, ArrowError> {
+    if field == "value" && column.data_type().is_floating() {
+        let floats = cast(column, &DataType::Float64)?;
+        let text = floats.as_primitive::<Float64Type>().iter().map(|value| value.map(|v| format!("{:.*}", DECIMALS as usize, v)));
+        return Ok(text.collect());
+    }
+    Ok(cast(column, &DataType::Utf8)?.as_string::<i32>().clone())
+}
+
+impl Columns {
+    fn new(batch: &RecordBatch, positions: &[Option<usize>]) -> Result<Self, ArrowError> {
+        let mut text = Vec::new();
+        for (field, position) in FIELDS.iter().zip(positions) {
+            text.push(match position {
+                Some(position) => Some(column_text(batch.column(*position), field)?),
+                None => None,
+            });
+        }
+
+        let date = positions[0].map(|position| batch.column(position));
+        let dates = match date.map(|column| column.data_type()) {
+            Some(DataType::Date32 | DataType::Date64 | DataType::Timestamp(..)) => {
+                Some(cast(date.unwrap(), &DataType::Date32)?.as_primitive().clone())
+            }
+            _ => None,
+        };
+        Ok(Columns { text, dates, rows: batch.num_rows() })
+    }
+
+    fn get(&self, field: usize, row: usize) -> Option<&str> {
+        self.text[field].as_ref().filter(|column| column.is_valid(row)).map(|column| column.value(row))
+    }
+}
+
+// One row of a record batch, the batch shared by its rows
+pub struct ColumnarRow {
+    columns: Arc<Columns>,
+    row: usize,  // In the batch
+    number: u64, // In the file, 1-based
+}
+
+// Rows of a Parquet or Arrow file, one record batch in memory at a time (plus the ones read ahead)
+pub struct ColumnarRecords {
+    batches: Batches,
+    positions: Vec<Option<usize>>,
+    batch: Option<Arc<Columns>>,
+    next_row: usize, // In the current batch
+    row: u64,        // Rows read so far in the file
+}
+
+pub type ColumnarStream = TransactionStream<ColumnarRecords>;
+
+impl RecordSource for ColumnarRecords {
+    type Record = Result<ColumnarRow, RowError>;
+
+    fn next_record(&mut self) -> Option<Self::Record> {
+        loop {
+            if let Some(columns) = self.batch.as_ref().filter(|columns| self.next_row < columns.rows) {
+                let row = ColumnarRow { columns: columns.clone(), row: self.next_row, number: self.row + 1 };
+                self.next_row += 1;
+                self.row += 1;
+                return Some(Ok(row));
+            }
+
+            let loaded = self.batches.next()?.and_then(|batch| Columns::new(&batch, &self.positions));
+            match loaded {
+                Ok(columns) => {
+                    self.batch = Some(Arc::new(columns));
+                    self.next_row = 0;
+                }
+                Err(error) => {
+                    // The rest of the file can't be located any more
+                    self.batches = Box::new(std::iter::empty());
+                    return Some(Err(RowError { line: None, fields: Vec::new(), reason: error.to_string() }));
+                }
+            }
+        }
+    }
+
+    // Text dates only, date columns need no detection
+    fn date_text<'r>(&self, record: &'r Self::Record) -> Option<&'r str> {
+        let record = record.as_ref().ok()?;
+        record.columns.dates.is_none().then(|| record.columns.get(0, record.row))?
+    }
+
+    fn parse(&self, record: &Self::Record, dates: &DateParser) -> Result<Transaction, RowError> {
+        let ColumnarRow { columns, row, .. } = record.as_ref().map_err(RowError::clone)?;
+        let row = *row;
+        let error = |reason: String| self.row_error(record, reason);
+        let required = |field: usize| columns.get(field, row).ok_or_else(|| error(format!("{} is missing", FIELDS[field])));
+
+        let date = match &columns.dates {
+            Some(dates) => dates.value_as_date(row).filter(|_| dates.is_valid(row)).ok_or_else(|| error("date is missing".to_string()))?,
+            None => dates.parse(required(0)?).map_err(error)?,
+        };
+        let value: Money = required(3)?.parse().map_err(error)?;
+        let count = required(4)?;
+        let transaction_count = count.trim().parse().map_err(|e| error(format!("invalid transaction_count '{}': {}", count, e)))?;
+
+        Ok(Transaction {
+            date,
+            domain: required(1)?.to_string(),
+            location: required(2)?.to_string(),
+            value,
+            transaction_count,
+            currency: columns.get(5, row).map(str::to_string),
+            source: None,
+        })
+    }
+
+    fn row_error(&self, record: &Self::Record, reason: String) -> RowError {
+        let Ok(ColumnarRow { columns, row, number }) = record else {
+            return RowError { line: None, fields: Vec::new(), reason }; // Unreadable batch
+        };
+        let fields = columns.text.iter().flatten().map(|column| if column.is_valid(*row) { column.value(*row).to_string() } else { String::new() }).collect();
+        RowError { line: Some(*number), fields, reason }
+    }
+}
+
+// Map the columns of any record batch reader and stream its rows
+fn columnar_stream<B: RecordBatchReader + Send + 'static>(reader: B, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    let positions = field_positions(&reader, schema)?;
+    let records = ColumnarRecords { batches: Box::new(reader), positions, batch: None, next_row: 0, row: 0 };
+    Ok(TransactionStream::new(records, schema))
+}
+
+// Parquet needs random access (the footer comes last), only the mapped columns are decoded
+fn parquet_stream<T: ChunkReader + 'static>(input: T, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(input)?;
+    let names: StringRecord = builder.schema().fields().iter().map(|field| field.name().as_str()).collect();
+    let mapped = schema.map_headers(&names)?;
+    let roots = mapped.iter().enumerate().filter(|(_, header)| !header.is_empty()).map(|(position, _)| position);
+    let projection = ProjectionMask::roots(builder.parquet_schema(), roots);
+    columnar_stream(builder.with_projection(projection).build()?, schema)
+}
+
+// Parquet content already in memory, e.g. read from a pipe
+pub fn read_parquet(bytes: Vec<u8>, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    parquet_stream(Bytes::from(bytes), schema)
+}
+
+// Arrow IPC file format from any seekable reader
+pub fn read_arrow_file<R: Read + Seek + Send + 'static>(source: R, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    columnar_stream(FileReader::try_new(source, None)?, schema)
+}
+
+// Arrow IPC stream format from any reader
+pub fn read_arrow_stream<R: Read + Send + 'static>(source: R, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    columnar_stream(StreamReader::try_new(source, None)?, schema)
+}
+
+// Errors prefixed with the file name, rows stamped with it
+fn with_path(path: &str, stream: Result<ColumnarStream, Box<dyn Error>>) -> Result<ColumnarStream, Box<dyn Error>> {
+    let mut stream = stream.map_err(|e| format!("{}: {}", path, e))?;
+    stream.source = Some(Arc::from(path));
+    Ok(stream)
+}
+
+// Open a Parquet file
+pub fn stream_parquet_file(path: &str, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    with_path(path, File::open(path).map_err(Into::into).and_then(|file| parquet_stream(file, schema)))
+}
+
+// Open an Arrow IPC file (.arrow, .feather v2)
+pub fn stream_arrow_file(path: &str, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    with_path(path, File::open(path).map_err(Into::into).and_then(|file| read_arrow_file(file, schema)))
+}
+
+// Open an Arrow IPC stream (.arrows), plain or compressed like the csv files
+pub fn stream_arrow_stream(path: &str, schema: &Schema) -> Result<ColumnarStream, Box<dyn Error>> {
+    with_path(path, open_input(path).and_then(|source| read_arrow_stream(source, schema)))
+}
+
+#[test]
+// Typed Parquet columns and text Arrow columns give the same transactions as a csv file
+fn test_columnar_inputs() {
+    use arrow::array::{Decimal128Array, Float64Array, Int64Array};
+    use arrow::datatypes::{Field, Schema as ArrowSchema};
+    use arrow::ipc::writer::FileWriter;
+    use chrono::NaiveDate;
+    use parquet::arrow::ArrowWriter;
+
+    let base = std::env::temp_dir().join(format!("bank_analysis_test_{}_columnar", std::process::id()));
+
+    // Parquet: real dates, decimal amounts, an extra column and a row without a location
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    let day = |d: u32| (NaiveDate::from_ymd_opt(2022, 1, d).unwrap() - epoch).num_days() as i32;
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("Date", Arc::new(Date32Array::from(vec![day(1), day(2)]))),
+        ("domain", Arc::new(StringArray::from(vec!["RETAIL ", "RETAIL"]))),
+        ("location", Arc::new(StringArray::from(vec![Some("Goa"), None]))),
+        ("value", Arc::new(Decimal128Array::from(vec![-1250, 100]).with_precision_and_scale(10, 2).unwrap())),
+        ("transaction_count", Arc::new(Int64Array::from(vec![3, 1]))),
+        ("batch_id", Arc::new(Int64Array::from(vec![7, 7]))),
+    ];
+    let batch = RecordBatch::try_from_iter(columns).unwrap();
+    let parquet_path = base.with_extension("parquet");
+    let mut writer = ArrowWriter::try_new(File::create(&parquet_path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let results: Vec<_> = stream_parquet_file(parquet_path.to_str().unwrap(), &Schema::default()).unwrap().collect();
+    std::fs::remove_file(&parquet_path).unwrap();
+    let row = results[0].as_ref().unwrap();
+    assert_eq!(row.date, NaiveDate::from_ymd_opt(2022, 1, 1).unwrap());
+    assert_eq!((row.domain.as_str(), row.value.to_string(), row.transaction_count), ("RETAIL", "-12.5".to_string(), 3));
+    assert_eq!(row.source.as_deref(), parquet_path.to_str());
+    let error = results[1].as_ref().unwrap_err();
+    assert_eq!((error.line, error.reason.as_str()), (Some(2), "location is missing"));
+    assert_eq!(error.fields, vec!["2022-01-02", "RETAIL", "", "1.00", "1"]);
+
+    // Lake columns with more decimals than Money: decimal(38,18) is read as long as the extra digits are zeros
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("date", Arc::new(Date32Array::from(vec![day(3), day(4)]))),
+        ("domain", Arc::new(StringArray::from(vec!["RETAIL", "RETAIL"]))),
+        ("location", Arc::new(StringArray::from(vec!["Goa", "Goa"]))),
+        ("value", Arc::new(Decimal128Array::from(vec![12_500_000_000_000_000_000, 1]).with_precision_and_scale(38, 18).unwrap())),
+        ("transaction_count", Arc::new(Int64Array::from(vec![1, 1]))),
+    ];
+    let batch = RecordBatch::try_from_iter(columns).unwrap();
+    let mut writer = ArrowWriter::try_new(File::create(&parquet_path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let results: Vec<_> = stream_parquet_file(parquet_path.to_str().unwrap(), &Schema::default()).unwrap().collect();
+    std::fs::remove_file(&parquet_path).unwrap();
+    assert_eq!(results[0].as_ref().unwrap().value.to_string(), "12.5");
+    assert!(results[1].as_ref().unwrap_err().reason.contains("more than 4 decimals"));
+
+    // Float amounts are rounded to 4 decimals, whatever binary noise or size they have
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("date", Arc::new(Date32Array::from(vec![day(3), day(4)]))),
+        ("domain", Arc::new(StringArray::from(vec!["RETAIL", "RETAIL"]))),
+        ("location", Arc::new(StringArray::from(vec!["Goa", "Goa"]))),
+        ("value", Arc::new(Float64Array::from(vec![0.1 + 0.2, 1e21]))),
+        ("transaction_count", Arc::new(Int64Array::from(vec![1, 1]))),
+    ];
+    let batch = RecordBatch::try_from_iter(columns).unwrap();
+    let mut writer = ArrowWriter::try_new(File::create(&parquet_path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let results: Vec<Transaction> = stream_parquet_file(parquet_path.to_str().unwrap(), &Schema::default()).unwrap().map(Result::unwrap).collect();
+    std::fs::remove_file(&parquet_path).unwrap();
+    assert_eq!(results[0].value.to_string(), "0.3");
+    assert_eq!(results[1].value.to_string(), "1000000000000000000000");
+
+    // Arrow IPC: everything as text, day-first dates detected
+    let text = |values: [&str; 2]| -> ArrayRef { Arc::new(StringArray::from(values.to_vec())) };
+    let fields = ["date", "domain", "location", "value", "transaction_count"];
+    let arrow_schema = Arc::new(ArrowSchema::new(fields.map(|name| Field::new(name, DataType::Utf8, true)).to_vec()));
+    let columns = vec![text(["05/01/2022", "25/01/2022"]), text(["RETAIL", "RETAIL"]), text(["Goa", "Bhuj"]), text(["10", "2.5"]), text(["1", "2"])];
+    let batch = RecordBatch::try_new(arrow_schema.clone(), columns).unwrap();
+    let arrow_path = base.with_extension("arrow");
+    let mut writer = FileWriter::try_new(File::create(&arrow_path).unwrap(), &arrow_schema).unwrap();
+    writer.write(&batch).unwrap();
+    writer.finish().unwrap();
+
+    let results: Vec<Transaction> = stream_arrow_file(arrow_path.to_str().unwrap(), &Schema::default()).unwrap().map(Result::unwrap).collect();
+    std::fs::remove_file(&arrow_path).unwrap();
+    assert_eq!(results[0].date, NaiveDate::from_ymd_opt(2022, 1, 5).unwrap());
+    assert_eq!(results[1].value.to_string(), "2.5");
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Deserializer};
+use std::error::Error;
+
+use bank_analysis::models::{Dimension, GroupStats};
+use bank_analysis::parser::load_toml_or_yaml;
+use crate::charts::ChartType;
+
+// Dashboard described in a TOML or YAML file instead of the built-in charts, see report.example.toml
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportConfig {
+    pub title: Option<String>,
+    pub columns: Option<usize>,   // Overrides --columns
+    pub chart_height: Option<u32>, // Overrides --chart-height
+    #[serde(rename = "section", alias = "sections", default)]
+    pub sections: Vec<SectionConfig>,
+}
+
+// One dashboard section: the rows are grouped on `by`, every panel plots one statistic of the groups
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SectionConfig {
+    pub heading: String,
+    #[serde(deserialize_with = "dimensions")]
+    pub by: Vec<Dimension>,
+    pub x_name: Option<String>, // x axis name, defaults to the grouped dimensions
+    pub columns: Option<usize>,
+    #[serde(rename = "panel", alias = "panels", default)]
+    pub panels: Vec<PanelConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PanelConfig {
+    pub title: String,
+    pub stat: Stat,
+    #[serde(rename = "type", default)]
+    pub chart_type: ChartType,
+    pub color: Option<String>, // Any CSS color, ECharts palette when missing
+    pub y_name: Option<String>, // y axis name, defaults to the statistic
+    pub name_gap: Option<i32>,  // Distance between the y axis and its name
+}
+
+// Statistics of a group that a panel can plot
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stat {
+    Total,
+    TransactionCount,
+    Average,
+    Median,
+    Count,
+}
+
+impl Stat {
+    pub fn of(&self, stats: &GroupStats) -> f64 {
+        match self {
+            Stat::Total => stats.total.to_f64(),
+            Stat::TransactionCount => stats.transaction_count as f64,
+            Stat::Average => stats.average.to_f64(),
+            Stat::Median => stats.median.to_f64(),
+            Stat::Count => stats.count as f64,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Stat::Total => "Total Transaction Value",
+            Stat::TransactionCount => "Number of Transactions",
+            Stat::Average => "Average Transaction Value",
+            Stat::Median => "Median Transaction Value",
+            Stat::Count => "Number of Rows",
+        }
+    }
+}
+
+// Same names as --by on the command line (domain, location, month, ...)
+fn dimensions<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Dimension>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    names.iter().map(|name| name.parse().map_err(serde::de::Error::custom)).collect()
+}
+
+impl ReportConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        load_toml_or_yaml(path, "config")
+    }
+}
+
+impl SectionConfig {
+    pub fn x_name(&self) -> String {
+        self.x_name.clone().unwrap_or_else(|| {
+            let names: Vec<&str> = self
+                .by
+                .iter()
+                .map(|dimension| match dimension {
+                    Dimension::Domain => "Domain",
+                    Dimension::Location => "City",
+                    Dimension::Period(granularity) => granularity.label(),
+                })
+                .collect();
+            if names.is_empty() { "All".to_string() } else { names.join(" / ") }
+        })
+    }
+}
+
+#[test]
+// The same dashboard written in TOML and YAML
+fn test_config_formats() {
+    let from_toml: ReportConfig = toml::from_str(
+        r#"
+        title = "Cities"
+        [[section]]
+        heading = "By City"
+        by = ["city", "quarter"]
+        [[section.panel]]
+        title = "Median by City"
+        stat = "median"
+        type = "bar"
+        color = "teal"
+        "#,
+    )
+    .unwrap();
+    let from_yaml: ReportConfig = serde_yaml_ng::from_str(
+        "
+title: Cities
+sections:
+  - heading: By City
+    by: [city, quarter]
+    panels:
+      - title: Median by City
+        stat: median
+        type: bar
+        color: teal
+",
+    )
+    .unwrap();
+
+    for config in [from_toml, from_yaml] {
+        let section = &config.sections[0];
+        assert_eq!(section.x_name(), "City / Quarter");
+        let panel = &section.panels[0];
+        assert!(matches!(panel.stat, Stat::Median));
+        assert!(matches!(panel.chart_type, ChartType::Bar));
+        assert_eq!(panel.color.as_deref(), Some("teal"));
+    }
+
+    // Typos are reported instead of silently ignored
+    assert!(toml::from_str::<ReportConfig>("[[section]]\nheading = \"x\"\nby = [\"citty\"]").is_err());
+}
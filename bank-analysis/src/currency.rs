@@ -0,0 +1,119 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+
+use crate::models::Transaction;
+use crate::money::{parse_decimal, Money};
+
+// Rates keep more decimals than amounts, e.g. 0.01198765 USD per INR
+const RATE_DECIMALS: u32 = 8;
+
+// One line of the exchange-rate CSV:
+// date,currency,rate
+// 2022-01-01,USD,74.4500
+// `rate` is the value of 1 unit of `currency` in the reporting currency, valid from `date` until the next line
+#[derive(Debug, Deserialize)]
+struct RateRecord {
+    date: NaiveDate,
+    currency: String,
+    rate: String, // Parsed as an exact decimal, not a float
+}
+
+// Dated exchange-rate table, currency code -> (valid from -> rate in 1/10^8 units)
+#[derive(Debug, Default)]
+pub struct ExchangeRates {
+    rates: HashMap<String, BTreeMap<NaiveDate, i128>>,
+}
+
+impl ExchangeRates {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_reader(file).map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    pub fn from_reader<R: Read>(source: R) -> Result<Self, Box<dyn Error>> {
+        let mut table = ExchangeRates::default();
+        for record in csv::Reader::from_reader(source).into_deserialize() {
+            let record: RateRecord = record?;
+            let rate = parse_decimal(&record.rate, RATE_DECIMALS)?;
+            if rate <= 0 {
+                return Err(format!("rate of {} on {} must be positive", record.currency, record.date).into());
+            }
+            table.rates.entry(normalize_code(&record.currency)).or_default().insert(record.date, rate);
+        }
+        Ok(table)
+    }
+
+    // Latest rate published on or before `date`
+    fn rate(&self, currency: &str, date: NaiveDate) -> Option<i128> {
+        let (_, rate) = self.rates.get(currency)?.range(..=date).next_back()?;
+        Some(*rate)
+    }
+}
+
+// Currency codes are compared upper case, without spaces
+fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+// Converts every row to one reporting currency, so rows from different branches can be summed
+// Rows without a currency are taken as already in the reporting currency
+pub struct CurrencyConverter {
+    reporting: String,
+    rates: ExchangeRates,
+}
+
+impl CurrencyConverter {
+    pub fn new(reporting: &str, rates: ExchangeRates) -> Self {
+        CurrencyConverter { reporting: normalize_code(reporting), rates }
+    }
+
+    pub fn convert(&self, mut tx: Transaction) -> Result<Transaction, Box<dyn Error>> {
+        let currency = match &tx.currency {
+            Some(code) if *code != self.reporting => code.clone(),
+            _ => {
+                tx.currency = Some(self.reporting.clone());
+                return Ok(tx);
+            }
+        };
+
+        let rate = self
+            .rates
+            .rate(&currency, tx.date)
+            .ok_or_else(|| format!("no {} to {} rate on or before {}", currency, self.reporting, tx.date))?;
+        let units = tx.value.units().checked_mul(rate).ok_or_else(|| format!("amount {} {} is too large to convert", tx.value, currency))?;
+        tx.value = Money::from_units(units).div_round(10i128.pow(RATE_DECIMALS)); // Rounded once, half away from zero
+        tx.currency = Some(self.reporting.clone());
+        Ok(tx)
+    }
+}
+
+#[test]
+// Each row uses the rate in force on its date
+fn test_currency_conversion() {
+    let rates = "\
+date,currency,rate
+2022-01-01,USD,74.5
+2022-02-01,usd,75.25
+2022-01-01,EUR,0.0001
+";
+    let converter = CurrencyConverter::new("INR", ExchangeRates::from_reader(rates.as_bytes()).unwrap());
+    let row = |date, value: &str, currency: Option<&str>| Transaction {
+        currency: currency.map(str::to_string),
+        ..Transaction::test_row(date, "RETAIL", "Goa", value)
+    };
+
+    let convert = |tx| converter.convert(tx).map(|tx| (tx.value.to_string(), tx.currency.unwrap()));
+    assert_eq!(convert(row((2022, 1, 31), "10.10", Some("USD"))).unwrap(), ("752.45".to_string(), "INR".to_string()));
+    assert_eq!(convert(row((2022, 3, 1), "-2", Some("USD"))).unwrap().0, "-150.5"); // Refund, February rate
+    assert_eq!(convert(row((2022, 1, 1), "0.5", Some("EUR"))).unwrap().0, "0.0001"); // 0.00005 rounded up
+    assert_eq!(convert(row((2022, 1, 1), "1000", None)).unwrap(), ("1000".to_string(), "INR".to_string()));
+    assert_eq!(convert(row((2022, 1, 1), "1000", Some("INR"))).unwrap().0, "1000");
+
+    // Before the first rate, or an unknown currency
+    assert!(convert(row((2021, 12, 31), "1", Some("USD"))).is_err());
+    assert!(convert(row((2022, 1, 1), "1", Some("GBP"))).is_err());
+}
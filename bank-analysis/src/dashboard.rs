@@ -0,0 +1,137 @@
+use charming::Chart;
+use std::fmt::Write;
+
+// A titled group of charts laid out on a grid
+pub struct Section {
+    heading: String,
+    columns: usize,
+    charts: Vec<Chart>,
+}
+
+impl Section {
+    pub fn new(heading: &str) -> Self {
+        Section { heading: heading.to_string(), columns: 1, charts: Vec::new() }
+    }
+
+    // Number of charts side by side (at least 1)
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    pub fn charts(mut self, charts: Vec<Chart>) -> Self {
+        self.charts.extend(charts);
+        self
+    }
+}
+
+// Whole HTML page built from the chart options directly, no rendered HTML is parsed or patched
+// Every chart gets its own <div> id from its position on the page
+pub struct Dashboard {
+    title: String,
+    chart_height: u32,
+    sections: Vec<Section>,
+}
+
+impl Dashboard {
+    pub fn new(title: &str) -> Self {
+        Dashboard { title: title.to_string(), chart_height: 800, sections: Vec::new() }
+    }
+
+    pub fn chart_height(mut self, height: u32) -> Self {
+        self.chart_height = height;
+        self
+    }
+
+    // Empty sections are skipped, so a chart group that wasn't selected leaves no heading behind
+    pub fn section(mut self, section: Section) -> Self {
+        if !section.charts.is_empty() {
+            self.sections.push(section);
+        }
+        self
+    }
+
+    // `scripts` loads ECharts in the <head> (CDN tags or the inlined library)
+    pub fn render(&self, scripts: &str) -> String {
+        let mut body = String::new();
+        let mut scripts_init = String::new();
+        let mut next_id = 1;
+
+        for section in &self.sections {
+            let _ = write!(
+                body,
+                "<section><h2>{}</h2><div class=\"grid\" style=\"grid-template-columns: repeat({}, 1fr);\">",
+                escape_html(&section.heading),
+                section.columns
+            );
+            for chart in &section.charts {
+                let id = format!("chart{}", next_id);
+                next_id += 1;
+                let _ = write!(body, "<div class=\"item\" id=\"{}\" style=\"height: {}px;\"></div>", id, self.chart_height);
+                // "</" can't appear inside a <script> block, JSON allows escaping the slash
+                let option = chart.to_string().replace("</", "<\\/");
+                let _ = writeln!(
+                    scripts_init,
+                    "echarts.init(document.getElementById('{}'), null, {{ renderer: 'canvas' }}).setOption({});",
+                    id, option
+                );
+            }
+            body.push_str("</div></section>");
+        }
+
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{title}</title>
+    {scripts}
+    <style>
+        body {{ font-family: sans-serif; margin: 0 auto; max-width: 1400px; padding: 0 20px; }}
+        h1, h2 {{ text-align: center; }}
+        .grid {{ display: grid; gap: 50px; }}
+        .item {{ width: 100%; }}
+    </style>
+</head>
+<body>
+    <h1>{title}</h1>
+    {body}
+    <div style="text-align: center; font-size: 14px; color: gray; margin-top: 30px;">
+        This report was AI-generated using Rust and Charming.
+    </div>
+    <script type="text/javascript">
+{init}    </script>
+</body>
+</html>
+"#,
+            title = escape_html(&self.title),
+            scripts = scripts,
+            body = body,
+            init = scripts_init,
+        )
+    }
+}
+
+// Headings and titles are plain text
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[test]
+// Ids are unique across sections and empty sections are dropped
+fn test_dashboard_ids() {
+    let html = Dashboard::new("Report")
+        .section(Section::new("City").charts(vec![Chart::new(), Chart::new()]))
+        .section(Section::new("Empty"))
+        .section(Section::new("Time <all>").columns(2).charts(vec![Chart::new()]))
+        .render("");
+
+    for id in ["chart1", "chart2", "chart3"] {
+        assert_eq!(html.matches(&format!("id=\"{}\"", id)).count(), 1);
+        assert_eq!(html.matches(&format!("getElementById('{}')", id)).count(), 1);
+    }
+    assert!(!html.contains("chart4"));
+    assert!(!html.contains("Empty"));
+    assert!(html.contains("<h2>Time &lt;all&gt;</h2>"));
+    assert!(html.contains("repeat(2, 1fr)"));
+}
@@ -0,0 +1,423 @@
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::json::reader::ReaderBuilder;
+use chrono::NaiveDate;
+use parquet::arrow::ArrowWriter;
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::anomaly::{Anomaly, Direction};
+use crate::models::{DomainStats, GroupStats, PeriodStats, RegionStats, Transaction};
+use crate::money::Money;
+use crate::normalize::Rewrite;
+use crate::parser::RowError;
+
+// File formats the results can be written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,      // One JSON array
+    JsonLines, // One JSON object per line
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    // File extension written for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    // Guess the format from the file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = path.rsplit('.').next()?.to_lowercase();
+        extension.parse().ok()
+    }
+}
+
+// Parse from the command line or a file extension
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "jsonl" | "ndjson" => Ok(ExportFormat::JsonLines),
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(format!("unknown export format '{}' (expected json, jsonl, csv or parquet)", s)),
+        }
+    }
+}
+
+// Row type that can be exported: its columns are declared here rather than taken from the rows,
+// so an empty file still has them and a column that is null everywhere keeps its type
+// Names and order must be the ones the Serialize impl writes
+pub trait Record: Serialize {
+    fn columns() -> Vec<Field>;
+}
+
+// Money is written as its decimal text (exact), dates as Date32
+fn column(name: &str, data_type: DataType) -> Field {
+    Field::new(name, data_type, false)
+}
+
+fn nullable(name: &str, data_type: DataType) -> Field {
+    Field::new(name, data_type, true)
+}
+
+// Flat row written for every anomaly, the field names and order are the export schema
+// Unbounded fences (one-sided fixed limits) are written as null / empty
+#[derive(Debug, Serialize)]
+pub struct AnomalyRecord<'a> {
+    pub index: usize,
+    pub date: NaiveDate,
+    pub domain: &'a str,
+    pub location: &'a str,
+    pub transaction_value: Money, // Exact decimal, written as text
+    pub transaction_count: u32,
+    pub metric: &'static str,
+    pub value: f64,
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub score: f64,
+    pub direction: &'static str,
+    pub group: String,
+    pub source: Option<&'a str>, // Input file of the row
+}
+
+impl Record for AnomalyRecord<'_> {
+    fn columns() -> Vec<Field> {
+        vec![
+            column("index", DataType::UInt64),
+            column("date", DataType::Date32),
+            column("domain", DataType::Utf8),
+            column("location", DataType::Utf8),
+            column("transaction_value", DataType::Utf8),
+            column("transaction_count", DataType::UInt32),
+            column("metric", DataType::Utf8),
+            column("value", DataType::Float64),
+            nullable("lower", DataType::Float64),
+            nullable("upper", DataType::Float64),
+            column("score", DataType::Float64),
+            column("direction", DataType::Utf8),
+            column("group", DataType::Utf8),
+            nullable("source", DataType::Utf8),
+        ]
+    }
+}
+
+impl<'a> AnomalyRecord<'a> {
+    pub fn new(tx: &'a Transaction, anomaly: &Anomaly) -> Self {
+        AnomalyRecord {
+            index: anomaly.index,
+            date: tx.date,
+            domain: &tx.domain,
+            location: &tx.location,
+            transaction_value: tx.value,
+            transaction_count: tx.transaction_count,
+            metric: anomaly.metric.id(),
+            value: anomaly.value,
+            lower: Some(anomaly.lower).filter(|v| v.is_finite()),
+            upper: Some(anomaly.upper).filter(|v| v.is_finite()),
+            score: anomaly.score,
+            direction: match anomaly.direction {
+                Direction::Above => "above",
+                Direction::Below => "below",
+            },
+            group: anomaly.group.to_string(),
+            source: tx.source.as_deref(),
+        }
+    }
+}
+
+// Row skipped by the lenient parsing, written to the quarantine file
+// `record` is the raw row as one csv line, so it can be fixed and pasted back
+#[derive(Debug, Serialize)]
+pub struct QuarantineRecord<'a> {
+    pub file: &'a str,
+    pub line: Option<u64>,
+    pub reason: &'a str,
+    pub record: String,
+}
+
+impl Record for QuarantineRecord<'_> {
+    fn columns() -> Vec<Field> {
+        vec![
+            column("file", DataType::Utf8),
+            nullable("line", DataType::UInt64),
+            column("reason", DataType::Utf8),
+            column("record", DataType::Utf8),
+        ]
+    }
+}
+
+impl<'a> QuarantineRecord<'a> {
+    pub fn new(file: &'a str, error: &'a RowError) -> Self {
+        let mut writer = csv::WriterBuilder::new().terminator(csv::Terminator::Any(b'\n')).from_writer(Vec::new());
+        let record = match writer.write_record(&error.fields).ok().and_then(|_| writer.into_inner().ok()) {
+            Some(bytes) if !error.fields.is_empty() => String::from_utf8_lossy(&bytes).trim_end().to_string(),
+            _ => String::new(), // Unreadable row, only the reason is known
+        };
+        QuarantineRecord { file, line: error.line, reason: &error.reason, record }
+    }
+}
+
+impl Record for Rewrite {
+    fn columns() -> Vec<Field> {
+        vec![
+            column("field", DataType::Utf8),
+            column("raw", DataType::Utf8),
+            column("normalized", DataType::Utf8),
+            column("match", DataType::Utf8),
+            column("rows", DataType::UInt64),
+        ]
+    }
+}
+
+// Region and domain tables only differ by their first column
+fn place_columns(name: &str) -> Vec<Field> {
+    vec![
+        column(name, DataType::Utf8),
+        column("total", DataType::Utf8),
+        column("average", DataType::Utf8),
+        column("median", DataType::Utf8),
+        column("count", DataType::UInt64),
+    ]
+}
+
+impl Record for RegionStats {
+    fn columns() -> Vec<Field> {
+        place_columns("region")
+    }
+}
+
+impl Record for DomainStats {
+    fn columns() -> Vec<Field> {
+        place_columns("domain")
+    }
+}
+
+impl Record for PeriodStats {
+    fn columns() -> Vec<Field> {
+        vec![
+            column("period", DataType::Utf8),
+            column("value", DataType::Utf8),
+            column("transaction_count", DataType::UInt64),
+            column("average", DataType::Utf8),
+            column("median", DataType::Utf8),
+            column("count", DataType::UInt64),
+        ]
+    }
+}
+
+impl Record for GroupStats {
+    fn columns() -> Vec<Field> {
+        vec![
+            column("group", DataType::Utf8),
+            column("total", DataType::Utf8),
+            column("transaction_count", DataType::UInt64),
+            column("average", DataType::Utf8),
+            column("median", DataType::Utf8),
+            column("count", DataType::UInt64),
+        ]
+    }
+}
+
+// Destination of the records, depending on the format
+enum Sink<W: Write + Send> {
+    Json { out: W, first: bool },
+    JsonLines(W),
+    Csv { writer: Box<csv::Writer<W>>, empty: bool }, // Boxed, the csv buffer is much larger than the other variants
+    Parquet { out: W, rows: Vec<Value> }, // Columnar, so the rows are kept until finish()
+}
+
+// Writes rows of one record type one at a time in the chosen format
+// Call finish() at the end, the JSON array is only closed there
+pub struct RecordWriter<W: Write + Send> {
+    sink: Sink<W>,
+    schema: SchemaRef, // Columns of the record type
+}
+
+// Parquet is written through Arrow: decode the rows into one batch of the record's schema
+fn write_parquet<W: Write + Send>(out: W, schema: SchemaRef, rows: &[Value]) -> Result<(), Box<dyn Error>> {
+    let mut writer = ArrowWriter::try_new(out, schema.clone(), None)?;
+    if !rows.is_empty() {
+        let mut decoder = ReaderBuilder::new(schema).build_decoder()?;
+        decoder.serialize(rows)?;
+        if let Some(batch) = decoder.flush()? {
+            writer.write(&batch)?;
+        }
+    }
+    writer.close()?;
+    Ok(())
+}
+
+impl RecordWriter<BufWriter<File>> {
+    // Create the file, the format comes from the extension unless given
+    pub fn create<T: Record>(path: &str, format: Option<ExportFormat>) -> Result<Self, Box<dyn Error>> {
+        let format = format
+            .or_else(|| ExportFormat::from_path(path))
+            .ok_or_else(|| format!("cannot tell the export format of '{}', use a .json, .jsonl, .csv or .parquet file", path))?;
+        Ok(RecordWriter::new::<T>(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+impl<W: Write + Send> RecordWriter<W> {
+    pub fn new<T: Record>(out: W, format: ExportFormat) -> Self {
+        let sink = match format {
+            ExportFormat::Json => Sink::Json { out, first: true },
+            ExportFormat::JsonLines => Sink::JsonLines(out),
+            // Header comes from the first record, or from the columns at finish() if there is none
+            ExportFormat::Csv => Sink::Csv { writer: Box::new(csv::Writer::from_writer(out)), empty: true },
+            ExportFormat::Parquet => Sink::Parquet { out, rows: Vec::new() },
+        };
+        RecordWriter { sink, schema: Arc::new(Schema::new(T::columns())) }
+    }
+
+    pub fn write<T: Record>(&mut self, record: &T) -> Result<(), Box<dyn Error>> {
+        match &mut self.sink {
+            Sink::Json { out, first } => {
+                out.write_all(if *first { b"[\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut *out, record)?;
+                *first = false;
+            }
+            Sink::JsonLines(out) => {
+                serde_json::to_writer(&mut *out, record)?;
+                out.write_all(b"\n")?;
+            }
+            Sink::Csv { writer, empty } => {
+                writer.serialize(record)?;
+                *empty = false;
+            }
+            Sink::Parquet { rows, .. } => rows.push(serde_json::to_value(record)?),
+        }
+        Ok(())
+    }
+
+    // Close the JSON array, write the Parquet file and flush everything
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.sink {
+            Sink::Json { mut out, first } => {
+                out.write_all(if first { b"[]\n" } else { b"\n]\n" })?;
+                out.flush()?;
+            }
+            Sink::JsonLines(mut out) => out.flush()?,
+            Sink::Csv { mut writer, empty } => {
+                if empty {
+                    writer.write_record(self.schema.fields().iter().map(|field| field.name()))?;
+                }
+                writer.flush()?;
+            }
+            Sink::Parquet { out, rows } => write_parquet(out, self.schema, &rows)?,
+        }
+        Ok(())
+    }
+}
+
+// Write a whole table (stats, ...) to one file
+pub fn write_records<T: Record>(path: &str, format: Option<ExportFormat>, records: &[T]) -> Result<(), Box<dyn Error>> {
+    let mut writer = RecordWriter::create::<T>(path, format)?;
+    for record in records {
+        writer.write(record)?;
+    }
+    writer.finish()
+}
+
+#[test]
+// Same anomaly in the three formats
+fn test_anomaly_export_formats() {
+    use crate::models::{GroupKey, Metric};
+
+    let tx = Transaction {
+        transaction_count: 2,
+        source: Some("feeds/goa.csv".into()),
+        ..Transaction::test_row((2022, 1, 1), "RETAIL", "Goa", "5000")
+    };
+    let anomaly = Anomaly {
+        index: 7,
+        metric: Metric::Value,
+        value: 5000.0,
+        lower: f64::NEG_INFINITY,
+        upper: 1000.0,
+        score: 4.0,
+        direction: Direction::Above,
+        group: GroupKey { domain: None, location: Some("Goa".to_string()), period: None },
+    };
+
+    let export = |format: ExportFormat| {
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new::<AnomalyRecord>(&mut buffer, format);
+        writer.write(&AnomalyRecord::new(&tx, &anomaly)).unwrap();
+        writer.finish().unwrap();
+        String::from_utf8(buffer).unwrap()
+    };
+
+    let line = r#"{"index":7,"date":"2022-01-01","domain":"RETAIL","location":"Goa","transaction_value":"5000","transaction_count":2,"metric":"value","value":5000.0,"lower":null,"upper":1000.0,"score":4.0,"direction":"above","group":"Goa","source":"feeds/goa.csv"}"#;
+    assert_eq!(export(ExportFormat::JsonLines), format!("{}\n", line));
+    assert_eq!(export(ExportFormat::Json), format!("[\n{}\n]\n", line));
+
+    let csv = export(ExportFormat::Csv);
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("index,date,domain,location,transaction_value,transaction_count,metric,value,lower,upper,score,direction,group,source"));
+    assert_eq!(lines.next(), Some("7,2022-01-01,RETAIL,Goa,5000,2,value,5000.0,,1000.0,4.0,above,Goa,feeds/goa.csv"));
+
+    assert_eq!(ExportFormat::from_path("out/anomalies.JSONL"), Some(ExportFormat::JsonLines));
+}
+
+#[test]
+// Stats table to Parquet and back, columns in struct order
+fn test_stats_parquet_export() {
+    use crate::models::RegionStats;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let stats = vec![
+        RegionStats { region: "Bhuj".to_string(), total: Money::from(300), average: Money::from(150), median: Money::from(150), count: 2 },
+        RegionStats { region: "Goa".to_string(), total: Money::from(100), average: Money::from(100), median: Money::from(100), count: 1 },
+    ];
+
+    let path = std::env::temp_dir().join(format!("bank_analysis_test_{}.parquet", std::process::id()));
+    let path = path.to_str().unwrap();
+    write_records(path, None, &stats).unwrap();
+
+    let file = File::open(path).unwrap();
+    let batches: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(file)
+        .unwrap()
+        .build()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    let schema = batches[0].schema();
+    let columns: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(columns, vec!["region", "total", "average", "median", "count"]);
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+}
+
+#[test]
+// Empty exports still have the columns, with the same types as a full one
+fn test_empty_export_schema() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let mut csv = Vec::new();
+    RecordWriter::new::<AnomalyRecord>(&mut csv, ExportFormat::Csv).finish().unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap(), "index,date,domain,location,transaction_value,transaction_count,metric,value,lower,upper,score,direction,group,source\n");
+
+    let mut parquet = Vec::new();
+    RecordWriter::new::<AnomalyRecord>(&mut parquet, ExportFormat::Parquet).finish().unwrap();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(parquet)).unwrap();
+    let schema = reader.schema();
+    assert_eq!(schema.fields().len(), 14);
+    assert_eq!(schema.field_with_name("date").unwrap().data_type(), &DataType::Date32);
+    assert_eq!(schema.field_with_name("lower").unwrap().data_type(), &DataType::Float64);
+    assert_eq!(schema.field_with_name("transaction_value").unwrap().data_type(), &DataType::Utf8);
+}
@@ -0,0 +1,454 @@
+use chrono::NaiveDate;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use crate::anomaly::{Anomaly, AnomalyDetector, GroupFenceAccumulator, GroupFences};
+use crate::currency::CurrencyConverter;
+use crate::models::{Dimension, Metric, Transaction};
+use crate::money::Money;
+use crate::normalize::{Normalizer, Rewrite};
+use crate::parser::{expand_inputs, stream_file, DateAmbiguity, InputFormat, RowError, Schema, STDIN};
+
+// Everything needed to read the inputs of a run, loaded once (schema, dictionary, rates) and shared by its passes
+pub struct InputReader {
+    pub inputs: Vec<String>,
+    pub format: Option<InputFormat>,
+    pub jobs: usize,
+    pub schema: Schema,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub normalizer: Normalizer,
+    pub converter: Option<CurrencyConverter>, // None keeps the amounts as they are in the files
+    pub lenient: bool,
+    pub max_error_rate: f64,
+    pub keep_stdin: bool, // Keep the rows of stdin in memory for the later passes, a pipe can only be read once
+    stdin: OnceLock<FileRead<KeptRows>>, // Stdin as the first pass read it
+}
+
+// Rows seen by one pass over the inputs, the malformed ones skipped in lenient mode (file, error)
+// the domain / location values rewritten by the normalization and the files whose dates fit several formats (file, ambiguity)
+#[derive(Default)]
+pub struct ReadSummary {
+    pub rows: usize,
+    pub skipped: Vec<(String, RowError)>,
+    pub rewrites: Vec<Rewrite>,
+    pub ambiguous_dates: Vec<(String, DateAmbiguity)>,
+}
+
+// More malformed rows than max_error_rate allows, with what was read so the skipped rows can still be quarantined
+pub struct ErrorRateExceeded {
+    pub summary: ReadSummary,
+    pub max_error_rate: f64,
+}
+
+impl fmt::Display for ErrorRateExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let skipped = &self.summary.skipped;
+        let rate = skipped.len() as f64 / self.summary.rows.max(1) as f64;
+        write!(
+            f,
+            "{} of {} rows are malformed ({:.2}%), more than --max-error-rate {}",
+            skipped.len(), self.summary.rows, rate * 100.0, self.max_error_rate
+        )?;
+        if let Some((file, error)) = skipped.first() {
+            write!(f, "; first one: {}: {}", file, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ErrorRateExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for ErrorRateExceeded {}
+
+// Rows outside their group's fence, one list per metric
+pub type Anomalies = Vec<Vec<(Transaction, Anomaly)>>;
+
+// Rows of stdin kept for the later passes: the amounts, the group keys and what the anomaly records show,
+// ~80 bytes per row, the domain / location / currency names shared
+struct KeptRow {
+    date: NaiveDate,
+    domain: Arc<str>,
+    location: Arc<str>,
+    value: Money,
+    transaction_count: u32,
+    currency: Option<Arc<str>>,
+}
+
+#[derive(Default)]
+struct KeptRows {
+    rows: Vec<KeptRow>,
+    names: HashSet<Arc<str>>,
+}
+
+impl KeptRows {
+    fn push(&mut self, tx: &Transaction) {
+        let mut name = |text: &str| match self.names.get(text) {
+            Some(name) => name.clone(),
+            None => {
+                let name: Arc<str> = Arc::from(text);
+                self.names.insert(name.clone());
+                name
+            }
+        };
+        let row = KeptRow {
+            date: tx.date,
+            domain: name(&tx.domain),
+            location: name(&tx.location),
+            value: tx.value,
+            transaction_count: tx.transaction_count,
+            currency: tx.currency.as_deref().map(name),
+        };
+        self.rows.push(row);
+    }
+}
+
+// What one file gave: the state the rows were folded into, plus what is reported about the input
+struct FileRead<S> {
+    state: S,
+    rows: usize,
+    skipped: Vec<(String, RowError)>,
+    ambiguous_dates: Vec<(String, DateAmbiguity)>,
+    normalizer: Normalizer,
+    currency: Option<String>, // Currency of the rows when they aren't converted
+}
+
+fn mixed_currencies(path: &str, seen: &str, currency: &str) -> String {
+    format!("{}: rows in {} and {}, pass --currency (and --rates) to convert them to one currency", path, seen, currency)
+}
+
+impl InputReader {
+    // Reader with the defaults of the command line: built-in normalization, no filters, one thread
+    pub fn new(inputs: &[String]) -> Self {
+        InputReader {
+            inputs: inputs.to_vec(),
+            format: None,
+            jobs: 1,
+            schema: Schema::default(),
+            from: None,
+            to: None,
+            normalizer: Normalizer::default(),
+            converter: None,
+            lenient: false,
+            max_error_rate: 0.05,
+            keep_stdin: false,
+            stdin: OnceLock::new(),
+        }
+    }
+
+    // Check a row against the date range
+    pub fn accepts(&self, tx: &Transaction) -> bool {
+        self.from.is_none_or(|from| tx.date >= from) && self.to.is_none_or(|to| tx.date <= to)
+    }
+
+    // Stdin again, from the rows the first pass kept: same rows, same skipped ones, same rewrites
+    fn replay<S>(&self, kept: &FileRead<KeptRows>, mut state: S, visit: &impl Fn(&mut S, Transaction)) -> FileRead<S> {
+        let source: Arc<str> = Arc::from(STDIN);
+        for row in &kept.state.rows {
+            let tx = Transaction {
+                date: row.date,
+                domain: row.domain.to_string(),
+                location: row.location.to_string(),
+                value: row.value,
+                transaction_count: row.transaction_count,
+                currency: row.currency.as_deref().map(str::to_string),
+                source: Some(source.clone()),
+            };
+            visit(&mut state, tx);
+        }
+        FileRead {
+            state,
+            rows: kept.rows,
+            skipped: kept.skipped.clone(),
+            ambiguous_dates: kept.ambiguous_dates.clone(),
+            normalizer: kept.normalizer.clone(),
+            currency: kept.currency.clone(),
+        }
+    }
+
+    // One file, on whichever thread picks it
+    fn read_file<S>(&self, path: &str, state: S, visit: &impl Fn(&mut S, Transaction)) -> Result<FileRead<S>, String> {
+        if path == STDIN && let Some(kept) = self.stdin.get() {
+            return Ok(self.replay(kept, state, visit));
+        }
+        let mut kept = (path == STDIN && self.keep_stdin).then(KeptRows::default);
+        let mut file = FileRead {
+            state,
+            rows: 0,
+            skipped: Vec::new(),
+            ambiguous_dates: Vec::new(),
+            normalizer: self.normalizer.clone(),
+            currency: None,
+        };
+        let mut rows = stream_file(path, &self.schema, self.format).map_err(|e| e.to_string())?;
+        file.ambiguous_dates.extend(rows.date_ambiguity().map(|ambiguity| (path.to_string(), ambiguity.clone())));
+
+        // Skip the row when lenient, else stop the run
+        let lenient = |skipped: &mut Vec<(String, RowError)>, error: RowError| {
+            if !self.lenient {
+                return Err(format!("{}: {}", path, error));
+            }
+            skipped.push((path.to_string(), error));
+            Ok(())
+        };
+        while let Some(result) = rows.next() {
+            file.rows += 1;
+            let tx = match result {
+                Ok(tx) => tx,
+                Err(error) => {
+                    lenient(&mut file.skipped, error)?;
+                    continue;
+                }
+            };
+            let tx = file.normalizer.apply(tx);
+            if !self.accepts(&tx) {
+                continue;
+            }
+            let tx = match &self.converter {
+                // A row without an exchange rate is malformed like one with a bad amount
+                Some(converter) => match converter.convert(tx) {
+                    Ok(tx) => tx,
+                    Err(error) => {
+                        lenient(&mut file.skipped, rows.row_error(error.to_string()))?;
+                        continue;
+                    }
+                },
+                None => {
+                    // Summing INR and USD amounts as they are would give a meaningless total
+                    if let Some(currency) = &tx.currency {
+                        match &file.currency {
+                            None => file.currency = Some(currency.clone()),
+                            Some(seen) if seen != currency => return Err(mixed_currencies(path, seen, currency)),
+                            Some(_) => {}
+                        }
+                    }
+                    tx
+                }
+            };
+            if let Some(kept) = &mut kept {
+                kept.push(&tx);
+            }
+            visit(&mut file.state, tx);
+        }
+
+        if let Some(kept) = kept {
+            let _ = self.stdin.set(FileRead {
+                state: kept,
+                rows: file.rows,
+                skipped: file.skipped.clone(),
+                ambiguous_dates: file.ambiguous_dates.clone(),
+                normalizer: file.normalizer.clone(),
+                currency: file.currency.clone(),
+            });
+        }
+        Ok(file)
+    }
+
+    // Stream every input file and fold the rows inside the date range into a state made by `new`
+    // The rows aren't kept here, only what the state collects (the group-bys keep every amount for their medians)
+    // Amounts arrive in the reporting currency when a converter is set
+    // A malformed row stops the run, unless lenient where it is skipped (up to max_error_rate, else ErrorRateExceeded)
+    // With jobs > 1 that many files are read at the same time, each into its own state; the states are merged
+    // in file order, so the results (the anomaly row numbers included) are the same as with one thread
+    pub fn for_each_transaction<S: Send>(
+        &self,
+        new: impl Fn() -> S + Sync,
+        visit: impl Fn(&mut S, Transaction) + Sync,
+        merge: impl Fn(&mut S, S),
+    ) -> Result<(S, ReadSummary), Box<dyn Error>> {
+        let mut total: Option<FileRead<S>> = None;
+        let mut add = |path: &str, file: FileRead<S>| -> Result<(), String> {
+            let Some(total) = &mut total else {
+                total = Some(file);
+                return Ok(());
+            };
+            if let (Some(seen), Some(currency)) = (&total.currency, &file.currency) && seen != currency {
+                return Err(mixed_currencies(path, seen, currency));
+            }
+            merge(&mut total.state, file.state);
+            total.rows += file.rows;
+            total.skipped.extend(file.skipped);
+            total.ambiguous_dates.extend(file.ambiguous_dates);
+            total.normalizer.merge(file.normalizer);
+            total.currency = total.currency.take().or(file.currency);
+            Ok(())
+        };
+
+        let paths = expand_inputs(&self.inputs)?;
+        if self.jobs <= 1 {
+            for path in &paths {
+                add(path, self.read_file(path, new(), &visit)?)?;
+            }
+        } else {
+            // Work queue: every thread takes the next file until none is left (or one has failed)
+            // A file is merged as soon as every file before it is, only the ones read out of order wait in `done`
+            let next = AtomicUsize::new(0);
+            let failed = AtomicBool::new(false);
+            let (sender, receiver) = channel();
+            thread::scope(|scope| -> Result<(), String> {
+                for _ in 0..self.jobs.min(paths.len()) {
+                    let sender = sender.clone();
+                    let (next, failed, paths, new, visit) = (&next, &failed, &paths, &new, &visit);
+                    scope.spawn(move || {
+                        while !failed.load(Ordering::Relaxed) {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(path) = paths.get(index) else { break };
+                            let result = self.read_file(path, new(), visit);
+                            failed.fetch_or(result.is_err(), Ordering::Relaxed);
+                            if sender.send((index, result)).is_err() {
+                                break; // Merging stopped on an error
+                            }
+                        }
+                    });
+                }
+                drop(sender);
+
+                let mut done: Vec<Option<Result<FileRead<S>, String>>> = paths.iter().map(|_| None).collect();
+                let mut merged = 0;
+                for (index, result) in receiver {
+                    done[index] = Some(result);
+                    while let Some(result) = done.get_mut(merged).and_then(Option::take) {
+                        // The first failed file in order is reported, like with one thread
+                        let failure = result.and_then(|file| add(&paths[merged], file));
+                        failed.fetch_or(failure.is_err(), Ordering::Relaxed);
+                        failure?;
+                        merged += 1;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        let Some(total) = total else {
+            return Ok((new(), ReadSummary::default())); // No files
+        };
+        let summary = ReadSummary {
+            rows: total.rows,
+            skipped: total.skipped,
+            rewrites: total.normalizer.rewrites(),
+            ambiguous_dates: total.ambiguous_dates,
+        };
+        let rate = if summary.rows > 0 { summary.skipped.len() as f64 / summary.rows as f64 } else { 0.0 };
+        if rate > self.max_error_rate {
+            return Err(Box::new(ErrorRateExceeded { summary, max_error_rate: self.max_error_rate }));
+        }
+        Ok((total.state, summary))
+    }
+
+    // Two passes: collect the metrics of every group, fit the detector inside every group,
+    // then stream the rows again and keep the ones outside their group's fence (one list per metric)
+    // The summary is the first pass's, the second one skips the same rows
+    pub fn find_anomalies(
+        &self,
+        metrics: &[Metric],
+        by: &[Dimension],
+        detector: &dyn AnomalyDetector,
+    ) -> Result<(Anomalies, ReadSummary), Box<dyn Error>> {
+        let (accumulators, summary) = self.for_each_transaction(
+            || -> Vec<GroupFenceAccumulator> { metrics.iter().map(|metric| GroupFenceAccumulator::new(*metric, by)).collect() },
+            |accumulators, tx| {
+                for acc in accumulators.iter_mut() {
+                    acc.add(&tx);
+                }
+            },
+            |accumulators, other| {
+                for (acc, more) in accumulators.iter_mut().zip(other) {
+                    acc.merge(more);
+                }
+            },
+        )?;
+        let fences: Vec<GroupFences> = accumulators.into_iter().map(|acc| acc.finish(detector)).collect();
+
+        // Rows are numbered within their file, then shifted by the rows of the files before it on merge
+        let ((_, anomalies), _) = self.for_each_transaction(
+            || -> (usize, Anomalies) { (0, vec![Vec::new(); fences.len()]) },
+            |(index, anomalies), tx| {
+                for (found, group_fences) in anomalies.iter_mut().zip(&fences) {
+                    if let Some(anomaly) = group_fences.check(*index, &tx) {
+                        found.push((tx.clone(), anomaly));
+                    }
+                }
+                *index += 1;
+            },
+            |(index, anomalies), (rows, more)| {
+                for (found, more) in anomalies.iter_mut().zip(more) {
+                    found.extend(more.into_iter().map(|(tx, anomaly)| (tx, Anomaly { index: anomaly.index + *index, ..anomaly })));
+                }
+                *index += rows;
+            },
+        )?;
+        Ok((anomalies, summary))
+    }
+}
+
+#[test]
+// Several files read by one or four threads: same totals, same anomalies with the same row numbers
+fn test_parallel_read() {
+    use crate::analysis::GroupAccumulator;
+    use crate::anomaly::Iqr;
+
+    let base = std::env::temp_dir().join(format!("bank_analysis_test_{}_parallel", std::process::id()));
+    std::fs::create_dir_all(&base).unwrap();
+    for file in 0..6 {
+        let mut data = String::from("date,domain,location,value,transaction_count\n");
+        for row in 0..50 {
+            let value = if (file * 50 + row) % 37 == 0 { 90000 } else { 1000 + (file * 7 + row * 13) % 200 };
+            data.push_str(&format!("1/{}/2022,RETAIL,{},{},{}\n", row % 28 + 1, ["Goa", "Bhuj"][row % 2], value, row % 5 + 1));
+        }
+        std::fs::write(base.join(format!("feed_{}.csv", file)), data).unwrap();
+    }
+
+    let read = |jobs| {
+        let reader = InputReader { jobs, ..InputReader::new(&[base.to_string_lossy().into_owned()]) };
+        let (groups, summary) = reader
+            .for_each_transaction(|| GroupAccumulator::new(&[Dimension::Location]), |groups, tx| groups.add(&tx), |groups, other| groups.merge(other))
+            .unwrap();
+        let totals: Vec<String> = groups.finish().iter().map(|stat| format!("{} {} {} {}", stat.key, stat.total, stat.transaction_count, stat.median)).collect();
+        let iqr = Iqr { k: 1.5 };
+        let (anomalies, _) = reader.find_anomalies(&[Metric::Value], &[Dimension::Location], &iqr).unwrap();
+        let found: Vec<(usize, String)> = anomalies[0].iter().map(|(tx, anomaly)| (anomaly.index, tx.value.to_string())).collect();
+        (summary.rows, totals, found)
+    };
+
+    let one = read(1);
+    let four = read(4);
+    std::fs::remove_dir_all(&base).unwrap();
+    assert_eq!(one.0, 300);
+    assert_eq!(one.1, four.1);
+    assert_eq!(one.2, four.2);
+    assert!(one.2.iter().any(|(index, _)| *index >= 250)); // Row of the last file, numbered after the 250 before it
+    assert!(one.2.iter().all(|(index, value)| value == "90000" && index % 37 == 0));
+}
+
+#[test]
+// A row without an exchange rate is reported with its file, line and raw fields; lenient runs skip it
+fn test_missing_rate() {
+    use crate::currency::ExchangeRates;
+
+    let base = std::env::temp_dir().join(format!("bank_analysis_test_{}_missing_rate", std::process::id()));
+    std::fs::create_dir_all(&base).unwrap();
+    let path = base.join("feed.csv").to_string_lossy().into_owned();
+    std::fs::write(&path, "date,domain,location,value,transaction_count,currency\n1/1/2022,RETAIL,Goa,100,1,INR\n1/2/2022,RETAIL,Goa,5,1,USD\n").unwrap();
+
+    let reader = InputReader { converter: Some(CurrencyConverter::new("INR", ExchangeRates::default())), ..InputReader::new(std::slice::from_ref(&path)) };
+    let read = |reader: &InputReader| reader.for_each_transaction(|| 0, |rows, _| *rows += 1, |rows, more| *rows += more);
+    let error = read(&reader).err().unwrap().to_string();
+    let lenient = InputReader { lenient: true, max_error_rate: 1.0, ..reader };
+    let (rows, summary) = read(&lenient).unwrap();
+    std::fs::remove_dir_all(&base).unwrap();
+
+    assert_eq!(error, format!("{}: line 3: no USD to INR rate on or before 2022-01-02", path));
+    assert_eq!((rows, summary.rows), (1, 2));
+    let (file, skipped) = &summary.skipped[0];
+    assert_eq!((file, skipped.line), (&path, Some(3)));
+    assert_eq!(skipped.fields, vec!["1/2/2022", "RETAIL", "Goa", "5", "1", "USD"]);
+}
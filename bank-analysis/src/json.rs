@@ -0,0 +1,175 @@
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::Arc;
+
+use crate::models::Transaction;
+use crate::parser::{open_input, DateParser, RecordSource, RowError, Schema, TransactionStream};
+
+// One JSON value as read, before it is turned into a transaction
+pub struct JsonRecord {
+    line: Option<u64>, // Line in JSON Lines, element number in an array
+    raw: String,       // Kept for the error report
+    value: Result<Value, String>,
+}
+
+type Records = Box<dyn Iterator<Item = JsonRecord> + Send>;
+
+// Skip the leading whitespace, returning the first byte after it (not consumed) and the number of line breaks skipped
+fn skip_whitespace<R: BufRead>(reader: &mut R) -> std::io::Result<(Option<u8>, u64)> {
+    let mut lines = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok((None, lines));
+        }
+        let end = buffer.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(buffer.len());
+        lines += buffer[..end].iter().filter(|b| **b == b'\n').count() as u64;
+        let first = buffer.get(end).copied();
+        reader.consume(end);
+        if first.is_some() {
+            return Ok((first, lines));
+        }
+    }
+}
+
+// JSON Lines are read one line at a time, a JSON array is read whole (one document, no way to resume after an error)
+fn read_records<R: Read + Send + 'static>(source: R) -> Result<Records, Box<dyn Error>> {
+    let mut reader = BufReader::new(source);
+    let (first, skipped) = skip_whitespace(&mut reader)?;
+    if first == Some(b'[') {
+        let values: Vec<Value> = serde_json::from_reader(reader)?;
+        let records = values.into_iter().zip(1..).map(|(value, number)| JsonRecord { line: Some(number), raw: value.to_string(), value: Ok(value) });
+        return Ok(Box::new(records.collect::<Vec<_>>().into_iter()));
+    }
+
+    let mut failed = false;
+    let records = reader
+        .lines()
+        .zip(skipped + 1..)
+        .filter(|(line, _)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map_while(move |(line, number)| {
+            if failed {
+                return None; // The rest of the file can't be located any more
+            }
+            Some(match line {
+                Ok(line) => {
+                    let value = serde_json::from_str(&line).map_err(|e| e.to_string());
+                    JsonRecord { line: Some(number), raw: line, value }
+                }
+                Err(error) => {
+                    failed = true;
+                    JsonRecord { line: Some(number), raw: String::new(), value: Err(error.to_string()) }
+                }
+            })
+        });
+    Ok(Box::new(records))
+}
+
+// Value of a field in one object, through the schema's column names
+fn field<'v>(schema: &Schema, object: &'v Map<String, Value>, field: &str) -> Option<&'v Value> {
+    object.iter().find(|(key, _)| schema.field_of(key) == Some(field)).map(|(_, value)| value)
+}
+
+// Objects of a JSON Lines file or a JSON array, keys matched through the schema
+pub struct JsonRecords {
+    records: Records,
+    schema: Schema,
+}
+
+pub type JsonStream = TransactionStream<JsonRecords>;
+
+impl RecordSource for JsonRecords {
+    type Record = JsonRecord;
+
+    fn next_record(&mut self) -> Option<JsonRecord> {
+        self.records.next()
+    }
+
+    fn date_text<'r>(&self, record: &'r JsonRecord) -> Option<&'r str> {
+        field(&self.schema, record.value.as_ref().ok()?.as_object()?, "date")?.as_str()
+    }
+
+    fn parse(&self, record: &JsonRecord, dates: &DateParser) -> Result<Transaction, RowError> {
+        let error = |reason: String| self.row_error(record, reason);
+        let value = record.value.as_ref().map_err(|reason| error(reason.clone()))?;
+        let Value::Object(object) = value else {
+            return Err(error(format!("expected a JSON object, found {}", value)));
+        };
+
+        // Amounts and counts can be numbers or strings, the other fields are strings
+        let text = |name: &str| match field(&self.schema, object, name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(text)) => Ok(Some(text.clone())),
+            Some(Value::Number(number)) if matches!(name, "value" | "transaction_count") => Ok(Some(number.to_string())),
+            Some(other) => Err(error(format!("{} must be a string, found {}", name, other))),
+        };
+        let required = |name: &str| text(name)?.ok_or_else(|| error(format!("{} is missing", name)));
+
+        let date = dates.parse(&required("date")?).map_err(error)?;
+        let value = required("value")?.parse().map_err(error)?;
+        let count = required("transaction_count")?;
+        let transaction_count = count.trim().parse().map_err(|e| error(format!("invalid transaction_count '{}': {}", count, e)))?;
+
+        Ok(Transaction {
+            date,
+            domain: required("domain")?,
+            location: required("location")?,
+            value,
+            transaction_count,
+            currency: text("currency")?,
+            source: None,
+        })
+    }
+
+    fn row_error(&self, record: &JsonRecord, reason: String) -> RowError {
+        let fields = if record.raw.is_empty() { Vec::new() } else { vec![record.raw.clone()] };
+        RowError { line: record.line, fields, reason }
+    }
+}
+
+// Wrap any reader into a transaction stream, JSON Lines or a JSON array depending on the first character
+pub fn read_json_transactions<R: Read + Send + 'static>(source: R, schema: &Schema) -> Result<JsonStream, Box<dyn Error>> {
+    Ok(TransactionStream::new(JsonRecords { records: read_records(source)?, schema: schema.clone() }, schema))
+}
+
+// Open a JSON Lines or JSON array file (plain, .gz, .zst or .bz2) and stream its objects
+pub fn stream_json_file(path: &str, schema: &Schema) -> Result<JsonStream, Box<dyn Error>> {
+    let source = open_input(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut stream = read_json_transactions(source, schema).map_err(|e| format!("{}: {}", path, e))?;
+    stream.source = Some(Arc::from(path));
+    Ok(stream)
+}
+
+#[test]
+// JSON Lines and a JSON array give the same transactions, bad objects are reported with their line
+fn test_json_inputs() {
+    use chrono::NaiveDate;
+
+    let lines = r#"
+{"date": "2022-01-05", "domain": " RETAIL", "location": "Goa", "value": 1000, "transaction_count": 2, "channel": "app"}
+{"date": "2022-01-06", "domain": "RETAIL", "location": "Bhuj", "value": "-12.50", "transaction_count": "1", "currency": "usd"}
+
+{"date": "2022-01-07", "domain": "RETAIL", "value": 3, "transaction_count": 1}
+not json
+"#;
+    let results: Vec<_> = read_json_transactions(lines.as_bytes(), &Schema::default()).unwrap().collect();
+    assert_eq!(results.len(), 4);
+    let row = results[0].as_ref().unwrap();
+    assert_eq!((row.date, row.domain.as_str()), (NaiveDate::from_ymd_opt(2022, 1, 5).unwrap(), "RETAIL"));
+    assert_eq!(row.value, 1000.into());
+    let row = results[1].as_ref().unwrap();
+    assert_eq!((row.value.to_string(), row.transaction_count, row.currency.as_deref()), ("-12.5".to_string(), 1, Some("USD")));
+
+    let error = results[2].as_ref().unwrap_err();
+    assert_eq!(error.to_string(), "line 5: location is missing");
+    assert_eq!(error.fields.len(), 1); // The whole object
+    assert_eq!(results[3].as_ref().unwrap_err().line, Some(6));
+
+    // Array, with keys renamed by the schema
+    let array = r#"[{"Txn Date": "25/01/2022", "domain": "RETAIL", "location": "Goa", "Amount": 2.5, "transaction_count": 1}]"#;
+    let schema: Schema = toml::from_str("[columns]\ndate = \"Txn Date\"\nvalue = \"Amount\"").unwrap();
+    let row = read_json_transactions(array.as_bytes(), &schema).unwrap().next().unwrap().unwrap();
+    assert_eq!(row.date, NaiveDate::from_ymd_opt(2022, 1, 25).unwrap());
+    assert_eq!(row.value.to_string(), "2.5");
+}
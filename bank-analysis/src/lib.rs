@@ -0,0 +1,11 @@
+pub mod parser;
+pub mod columnar;
+pub mod json;
+pub mod analysis;
+pub mod models;
+pub mod currency;
+pub mod money;
+pub mod normalize;
+pub mod anomaly;
+pub mod export;
+pub mod input;
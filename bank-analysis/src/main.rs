@@ -1,398 +1,316 @@
-mod parser;
-mod analysis;
-mod models;
-
-// Charming for visualization
-use charming::{
-    component::{Axis, Title},
-    datatype::{CompositeValue, NumericValue},
-    element::{AxisLabel, AxisType, NameLocation, Tooltip, ItemStyle},
-    series::{Scatter, Line},
-    Chart, HtmlRenderer,
-};
+mod charts;
+mod cli;
+mod config;
+mod dashboard;
+
+use clap::Parser;
+use std::error::Error;
 use std::fs::write;
+use std::path::Path;
+
+use bank_analysis::input::{ErrorRateExceeded, InputReader, ReadSummary};
+use bank_analysis::parser::DateAmbiguity;
+use bank_analysis::analysis::{RegionAccumulator, DomainAccumulator, PeriodAccumulator, GroupAccumulator};
+use bank_analysis::export::{write_records, AnomalyRecord, ExportFormat, QuarantineRecord, RecordWriter};
+use bank_analysis::models::{DomainStats, PeriodStats, RegionStats};
+use crate::charts::{create_charts, create_domain_charts, create_time_series_graph, panel_chart};
+use crate::config::ReportConfig;
+use crate::dashboard::{Dashboard, Section};
+use crate::cli::{Cli, Command, InputArgs, ReportArgs, AnomalyArgs, StatsArgs, ChartGroup};
+
+// ECharts bundled with the binary (Apache-2.0, same build charming targets), for reports opened offline
+const ECHARTS_JS: &str = include_str!("../assets/echarts-5.4.2.min.js");
+
+// <script> tags loading ECharts, either from the CDN or inlined in the page
+// echarts-gl is only needed for 3D charts, none of ours use it, so it isn't bundled
+fn script_tags(offline: bool) -> String {
+    if offline {
+        format!("<script>{}</script>", ECHARTS_JS)
+    } else {
+        concat!(
+            r#"<script src="https://cdn.jsdelivr.net/npm/echarts@5.4.2/dist/echarts.min.js"></script>"#,
+            r#"<script src="https://cdn.jsdelivr.net/npm/echarts-gl@2.0.9/dist/echarts-gl.min.js"></script>"#,
+        )
+        .to_string()
+    }
+}
+
+// Tell how many rows were skipped or rewritten and write the quarantine / normalization files, once per run
+fn report_input(input: &InputArgs, summary: &ReadSummary) -> Result<(), Box<dyn Error>> {
+    // One warning per pair of formats, however many files (and passes) ran into it
+    let same = |a: &DateAmbiguity, b: &DateAmbiguity| a.format == b.format && a.others == b.others;
+    let mut warned: Vec<&DateAmbiguity> = Vec::new();
+    for (file, ambiguity) in &summary.ambiguous_dates {
+        if warned.iter().any(|seen| same(seen, ambiguity)) {
+            continue;
+        }
+        warned.push(ambiguity);
+        let more = summary.ambiguous_dates.iter().filter(|(_, other)| same(other, ambiguity)).count() - 1;
+        eprintln!(
+            "Warning: dates like '{}' in {}{} fit {} and {}, reading them as {} (pass --date-format if that is wrong)",
+            ambiguity.example,
+            file,
+            if more > 0 { format!(" and {} other file(s)", more) } else { String::new() },
+            ambiguity.format,
+            ambiguity.others.join(" and "),
+            ambiguity.format
+        );
+    }
 
-use crate::parser::load_csv_file;
-use crate::analysis::{compute_region_stats, aggregate_by_month, detect_anomaly_for_value, detect_anomaly_for_transaction_count};
-use crate::models::{RegionStats, MonthStats};
-
-fn create_time_series_graph(month_stats: Vec<MonthStats>) -> Vec<Chart> {
-    let data: Vec<Vec<CompositeValue>> = month_stats
-        .into_iter()
-        .map(|stat| {
-            vec![
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.month as f64)),
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.value as f64)),
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.transaction_count as f64)),    
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.average)),
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.median)),
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.count as f64)),
-            ]
-        })
-        .collect();
-
-    let mut charts = Vec::new();
-
-    // Chart 1: Transaction value
-    charts.push(
-        Chart::new()
-            .title(Title::new().text("Transaction Value by Date (2022)").left("center"))
-            .tooltip(Tooltip::new())
-            .x_axis(
-                Axis::new()
-                    .type_(AxisType::Value) 
-                    .name("Month")
-                    .data(
-                        data.iter()
-                            .map(|row| match &row[0] {
-                                CompositeValue::Number(n) => match n {
-                                    NumericValue::Float(f) => format!("{}", *f as i32),
-                                    &NumericValue::Integer(_) => todo!(),
-                                },
-                                _ => panic!("Expected number for month"),
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                    .axis_label(AxisLabel::new().rotate(0).interval(0))
-            )
-            .y_axis(
-                Axis::new()
-                    .type_(AxisType::Value)
-                    .name("Transaction Value")
-                    .name_location(NameLocation::Middle)
-                    .name_gap(105)
-                    .min(55_000_000_000.0)
-                    .max(66_000_000_000.0)
-            )
-            .series(
-                Line::new()
-                    .data(
-                        data.iter()
-                            .map(|row| {
-                                CompositeValue::Array(vec![
-                                    row[0].clone(),  // x: month
-                                    row[1].clone(),  // y: transaction value
-                                ])
-                            })
-                            .collect::<Vec<_>>()
-                    )
-            ),
-    );
-
-    // Chart 2: Transaction count
-    charts.push(
-        Chart::new()
-            .title(Title::new().text("Total Number of Transactions by Date (2022)").left("center"))
-            .tooltip(Tooltip::new())
-            .x_axis(
-                Axis::new()
-                    .type_(AxisType::Value) 
-                    .name("Month")
-                    .data(
-                        data.iter()
-                            .map(|row| match &row[0] {
-                                CompositeValue::Number(n) => match n {
-                                    NumericValue::Float(f) => format!("{}", *f as i32),
-                                    &NumericValue::Integer(_) => todo!(),
-                                },
-                                _ => panic!("Expected number for month"),
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                    .axis_label(AxisLabel::new().rotate(0).interval(0))
-            )
-            .y_axis(
-                Axis::new()
-                    .type_(AxisType::Value)
-                    .name("Number of Transactions")
-                    .name_location(NameLocation::Middle)
-                    .name_gap(100)
-                    .min(110_000_000.0)
-                    .max(130_000_000.0)                    
-            )
-            .series(
-                Line::new()
-                    .data(
-                        data.iter()
-                            .map(|row| {
-                                CompositeValue::Array(vec![
-                                    row[0].clone(),  // x: month
-                                    row[2].clone(),  // y: transaction count
-                                ])
-                            })
-                            .collect::<Vec<_>>()
-                    )
-            ),
-    );
-
-    charts
+    if let Some(path) = &input.quarantine {
+        let records: Vec<QuarantineRecord> = summary.skipped.iter().map(|(file, error)| QuarantineRecord::new(file, error)).collect();
+        write_records(path, ExportFormat::from_path(path).or(Some(ExportFormat::Csv)), &records)?;
+    }
+    if !summary.skipped.is_empty() {
+        eprintln!(
+            "Skipped {} malformed row(s) of {}{}",
+            summary.skipped.len(),
+            summary.rows,
+            input.quarantine.as_ref().map(|path| format!(", see {}", path)).unwrap_or_default()
+        );
+    }
+
+    if let Some(path) = &input.normalization_report {
+        write_records(path, ExportFormat::from_path(path).or(Some(ExportFormat::Csv)), &summary.rewrites)?;
+    }
+    if !summary.rewrites.is_empty() {
+        eprintln!(
+            "Normalized {} raw domain/location value(s) on {} row(s){}",
+            summary.rewrites.len(),
+            summary.rewrites.iter().map(|rewrite| rewrite.rows).sum::<usize>(),
+            input.normalization_report.as_ref().map(|path| format!(", see {}", path)).unwrap_or_default()
+        );
+    }
+    Ok(())
 }
 
-fn create_charts(region_stats: Vec<RegionStats>) -> Vec<Chart> {
-    // Prepare data for the dataset
-    let data: Vec<Vec<CompositeValue>> = region_stats
-        .into_iter()
-        .map(|stat| {
-            vec![
-                CompositeValue::String(stat.region),
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.total as f64)),
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.average)),    
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.median)),
-                CompositeValue::Number(charming::datatype::NumericValue::Float(stat.count as f64)),
-            ]
-        })
-        .collect();
-
-    let mut charts = Vec::new();
-
-    // Chart 1: Total
-    charts.push(
-        Chart::new()
-            .title(Title::new().text("Total Transaction Value by City").left("center"))
-            .tooltip(Tooltip::new())
-            .x_axis(
-                Axis::new()
-                    .type_(AxisType::Category)
-                    .name("City")
-                    .data(
-                        data.iter()
-                            .map(|row| match &row[0] {
-                                CompositeValue::String(s) => s.clone(),
-                                _ => panic!("Expected string for city"),
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                    .axis_label(AxisLabel::new().rotate(45).interval(0)),
-            )
-            .y_axis(
-                Axis::new()
-                    .type_(AxisType::Value)
-                    .name("Total Transaction Value")
-                    .name_location(NameLocation::Middle)
-                    .name_gap(100)
-                    .min(15_000_000_000.0)
-                    .max(18_000_000_000.0)
-            )
-            .series(
-                Scatter::new()
-                    .name("Transaction Value")
-                    .data(
-                        data.iter()
-                            .map(|row| row[1].clone())
-                            .collect::<Vec<_>>(),
-                    )
-                    .item_style(ItemStyle::new().color("orange"))
-            ),
-    );
-
-    // Chart 2: Average
-    charts.push(
-        Chart::new()
-            .title(Title::new().text("Average Transaction Value by City").left("center"))
-            .tooltip(Tooltip::new())
-            .x_axis(
-                Axis::new()
-                    .type_(AxisType::Category)
-                    .name("City")
-                    .data(
-                        data.iter()
-                            .map(|row| match &row[0] {
-                                CompositeValue::String(s) => s.clone(),
-                                _ => panic!("Expected string for city"),
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                    .axis_label(AxisLabel::new().rotate(45).interval(0)),
-            )
-            .y_axis(
-                Axis::new()
-                    .type_(AxisType::Value)
-                    .name("Average Transaction Value")
-                    .name_location(NameLocation::Middle)
-                    .name_gap(70)
-                    .min(700_000.0)
-                    .max(800_000.0)
-            )
-            .series(
-                Scatter::new()
-                    .name("Transaction Value")
-                    .data(
-                        data.iter()
-                            .map(|row| row[2].clone())
-                            .collect::<Vec<_>>(),
-                    )
-                    .item_style(ItemStyle::new().color("purple"))
-            ),
-    );
-
-    // Chart 3: Median
-    charts.push(
-        Chart::new()
-            .title(Title::new().text("Median Transaction Value by City").left("center"))
-            .tooltip(Tooltip::new())
-            .x_axis(
-                Axis::new()
-                    .type_(AxisType::Category)
-                    .name("City")
-                    .data(
-                        data.iter()
-                            .map(|row| match &row[0] {
-                                CompositeValue::String(s) => s.clone(),
-                                _ => panic!("Expected string for city"),
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                    .axis_label(AxisLabel::new().rotate(45).interval(0)),
-            )
-            .y_axis(
-                Axis::new()
-                    .type_(AxisType::Value)
-                    .name("Median Transaction Value")
-                    .name_location(NameLocation::Middle)
-                    .name_gap(70)
-                    .min(700_000.0)
-                    .max(800_000.0)
-            )
-            .series(
-                Scatter::new()
-                    .name("Transaction Value")
-                    .data(
-                        data.iter()
-                            .map(|row| row[3].clone())
-                            .collect::<Vec<_>>(),
-                    )
-                    .item_style(ItemStyle::new().color("green"))
-            ),
-    );
-
-    // Chart 4: Count
-    charts.push(
-        Chart::new()
-            .title(Title::new().text("Total Number of Transactions by City").left("center"))
-            .tooltip(Tooltip::new())
-            .x_axis(
-                Axis::new()
-                    .type_(AxisType::Category)
-                    .name("City")
-                    .data(
-                        data.iter()
-                            .map(|row| match &row[0] {
-                                CompositeValue::String(s) => s.clone(),
-                                _ => panic!("Expected string for city"),
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                    .axis_label(AxisLabel::new().rotate(45).interval(0)),
-            )
-            .y_axis(
-                Axis::new()
-                    .type_(AxisType::Value)
-                    .name("Total Transactions")
-                    .name_location(NameLocation::Middle)
-                    .name_gap(60)
-                    .min(20_000.0)
-                    .max(25_000.0)
-            )
-            .series(
-                Scatter::new()
-                    .name("Transactions")
-                    .data(
-                        data.iter()
-                            .map(|row| row[4].clone())
-                            .collect::<Vec<_>>(),
-                    )
-                    .item_style(ItemStyle::new().color("red"))
-            ),
-    );
-
-    charts
+// stats.html + region_stats + csv -> stats_region_stats.csv, in the same folder as the HTML file
+fn stats_export_path(output: &str, table: &str, format: ExportFormat) -> String {
+    let output = Path::new(output);
+    let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("stats");
+    output
+        .with_file_name(format!("{}_{}.{}", stem, table, format.extension()))
+        .to_string_lossy()
+        .into_owned()
 }
 
-fn main() {
-    let transactions = load_csv_file("../data/bankdataset.csv").expect("Failed to load");
+// The original dashboard: city, domain and time series charts
+fn builtin_dashboard(region_stats: &[RegionStats], domain_stats: &[DomainStats], period_stats: &[PeriodStats], args: &ReportArgs) -> Dashboard {
+    let scale = args.axis_scale();
+    let mut dashboard = Dashboard::new("Region Statistics").chart_height(args.chart_height);
 
-    let region_stats = compute_region_stats(&transactions);
+    // Create scatter plot for region statistics
+    if args.wants(ChartGroup::Region) {
+        dashboard = dashboard.section(Section::new("By City").columns(args.columns).charts(create_charts(region_stats, scale)));
+    }
 
-    let month_stats = aggregate_by_month(&transactions);
+    // Create scatter plot for domain statistics
+    if args.wants(ChartGroup::Domain) {
+        dashboard = dashboard.section(Section::new("By Domain").columns(args.columns).charts(create_domain_charts(domain_stats, scale)));
+    }
 
     // Create line graph for date statistics
-    let time_charts = create_time_series_graph(month_stats);
+    if args.wants(ChartGroup::Time) {
+        let heading = format!("By {}", args.granularity.label());
+        let charts = create_time_series_graph(period_stats, args.granularity, scale);
+        dashboard = dashboard.section(Section::new(&heading).columns(args.columns).charts(charts));
+    }
+    dashboard
+}
 
-    // Create scatter plot for region statistics
-    let charts = create_charts(region_stats);
-
-    let all_charts: Vec<Chart> = [charts, time_charts].into_iter().flatten().collect();
-
-    // Render each chart and combine HTML outputs
-    let renderer = HtmlRenderer::new("Region Statistics", 1200, 800);
-    let mut html_output = String::new();
-    for (i, chart) in all_charts.iter().enumerate() {
-        let chart_id = format!("chart{}", i + 1);
-        let html = renderer
-            .render(chart)
-            .expect(&format!("Failed to render chart {}", i + 1));
-        // Extract only the <div> and <script> parts, removing <!DOCTYPE> and <html>
-        let start = html.find("<div class=\"container\"").unwrap_or(0);
-        let end = html.rfind("</script>").unwrap_or(html.len()) + 9;
-        let chart_html = &html[start..end];
-        // Replace default 'chart' ID with unique chart_id
-        let modified_html = chart_html
-            .replace(r#"id="chart""#, &format!(r#"id="{}""#, chart_id))
-            .replace(
-                r#"document.getElementById('chart')"#, 
-                &format!(r#"document.getElementById('{}')"#, chart_id),
-            );
-        html_output.push_str(&modified_html);
-        html_output.push_str("<div style='margin-bottom: 50px;'></div>");
+// Dashboard described by a config file, every panel plots one statistic of its section's groups
+fn configured_dashboard(config: &ReportConfig, sections: Vec<GroupAccumulator>, args: &ReportArgs) -> Dashboard {
+    let scale = args.axis_scale();
+    let title = config.title.as_deref().unwrap_or("Region Statistics");
+    let mut dashboard = Dashboard::new(title).chart_height(config.chart_height.unwrap_or(args.chart_height));
+
+    for (section, acc) in config.sections.iter().zip(sections) {
+        let stats = acc.finish();
+        let labels: Vec<String> = stats.iter().map(|stat| stat.key.to_string()).collect();
+        let x_name = section.x_name();
+        let charts = section
+            .panels
+            .iter()
+            .map(|panel| panel_chart(panel, &x_name, &labels, stats.iter().map(|stat| panel.stat.of(stat)).collect(), scale))
+            .collect();
+        let columns = section.columns.or(config.columns).unwrap_or(args.columns);
+        dashboard = dashboard.section(Section::new(&section.heading).columns(columns).charts(charts));
     }
+    dashboard
+}
 
-    // Wrap in a single HTML structure (The html content below is AI-generated)
-    let final_html = format!(
-        r#"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <meta charset="utf-8">
-            <title>Region Statistics</title>
-            <script src="https://cdn.jsdelivr.net/npm/echarts@5.4.2/dist/echarts.min.js"></script>
-            <script src="https://cdn.jsdelivr.net/npm/echarts-gl@2.0.9/dist/echarts-gl.min.js"></script>
-            <style>
-                .container {{ display: flex; justify-content: center; align-items: center; }}
-                .item {{ margin: auto; }}
-            </style>
-        </head>
-        <body>
-            {}
-            <div style="text-align: center; font-size: 14px; color: gray; margin-top: 30px;">
-                This report was AI-generated using Rust and Charming.
-            </div>
-        </body>
-        </html>
-        "#,
-        html_output
-    );
+// Build the HTML dashboard
+// The commands return what they read, main reports it once even when the default run does two commands
+fn run_report(input: &InputReader, args: &ReportArgs) -> Result<ReadSummary, Box<dyn Error>> {
+    // Read the dashboard config first, so a mistake in it fails before the pass
+    let config = args.config.as_deref().map(ReportConfig::load).transpose()?;
+
+    // Stream the rows once and feed every accumulator (plus one per configured section)
+    let ((regions, domains, periods, sections), summary) = input.for_each_transaction(
+        || {
+            let sections: Vec<GroupAccumulator> =
+                config.iter().flat_map(|c| &c.sections).map(|section| GroupAccumulator::new(&section.by)).collect();
+            (RegionAccumulator::default(), DomainAccumulator::default(), PeriodAccumulator::new(args.granularity), sections)
+        },
+        |(regions, domains, periods, sections), tx| {
+            regions.add(&tx);
+            domains.add(&tx);
+            periods.add(&tx);
+            for acc in sections.iter_mut() {
+                acc.add(&tx);
+            }
+        },
+        |(regions, domains, periods, sections), other| {
+            regions.merge(other.0);
+            domains.merge(other.1);
+            periods.merge(other.2);
+            for (acc, more) in sections.iter_mut().zip(other.3) {
+                acc.merge(more);
+            }
+        },
+    )?;
+
+    let region_stats = regions.finish();
+    let domain_stats = domains.finish();
+    let period_stats = periods.finish();
+
+    // Write the stats tables next to the HTML file
+    for format in &args.export_stats {
+        write_records(&stats_export_path(&args.output, "region_stats", *format), Some(*format), &region_stats)?;
+        write_records(&stats_export_path(&args.output, "domain_stats", *format), Some(*format), &domain_stats)?;
+        write_records(&stats_export_path(&args.output, "period_stats", *format), Some(*format), &period_stats)?;
+    }
+
+    let dashboard = match &config {
+        Some(config) => configured_dashboard(config, sections, args),
+        None => builtin_dashboard(&region_stats, &domain_stats, &period_stats, args),
+    };
+
+    let final_html = dashboard.render(&script_tags(args.offline));
 
     // Write to file
-    write("stats.html", final_html).expect("Failed to write HTML file");
-
-    // Anomaly detection output
-    // Find the rows that are considered outliers
-    // Loop over the rows and print every fields
-    let value_anomalies = detect_anomaly_for_value(&transactions);
-    println!("Anomalies by Transaction Value ({} found):", value_anomalies.len());
-    for anomaly in &value_anomalies {
-        println!(
-            "Date: {}, Location: {}, Domain: {}, Value: {}",
-            anomaly.date, anomaly.location, anomaly.domain, anomaly.value
-        );
+    write(&args.output, final_html)?;
+    Ok(summary)
+}
+
+// Anomaly detection output
+fn run_anomalies(input: &InputReader, args: &AnomalyArgs) -> Result<ReadSummary, Box<dyn Error>> {
+    // Open the export files first, so a bad path fails before the long passes
+    let writers = args
+        .export
+        .iter()
+        .map(|path| RecordWriter::create::<AnomalyRecord>(path, args.export_format))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Two passes over the files, fitting the selected detector inside every group in between
+    let detector = args.detector();
+    let (anomalies, summary) = input.find_anomalies(&args.metric, &args.by, detector.as_ref())?;
+
+    // Loop over the rows and print every fields, plus the group baseline that was violated
+    for (i, (metric, found)) in args.metric.iter().zip(&anomalies).enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("Anomalies by {} ({} found):", metric.label(), found.len());
+        if args.quiet {
+            continue;
+        }
+        for (tx, anomaly) in found {
+            println!(
+                "Row: {}, Date: {}, Location: {}, Domain: {}, {}: {}, Group: {} ({:.2} to {:.2}), {:?} by score {:.2}, Source: {}",
+                anomaly.index, tx.date, tx.location, tx.domain, metric.label(), anomaly.value,
+                anomaly.group, anomaly.lower, anomaly.upper, anomaly.direction, anomaly.score,
+                tx.source.as_deref().unwrap_or("-")
+            );
+        }
     }
 
-    let count_anomalies = detect_anomaly_for_transaction_count(&transactions);
-    println!("\nAnomalies by Transaction Count ({} found):", count_anomalies.len());
-    for anomaly in &count_anomalies {
+    // Machine-readable copies for downstream tools
+    for mut writer in writers {
+        for (tx, anomaly) in anomalies.iter().flatten() {
+            writer.write(&AnomalyRecord::new(tx, anomaly))?;
+        }
+        writer.finish()?;
+    }
+    Ok(summary)
+}
+
+// Print the group-by statistics as a tab separated table
+fn run_stats(input: &InputReader, args: &StatsArgs) -> Result<ReadSummary, Box<dyn Error>> {
+    let (groups, summary) = input.for_each_transaction(
+        || GroupAccumulator::new(&args.by),
+        |groups, tx| groups.add(&tx),
+        |groups, other| groups.merge(other),
+    )?;
+    let stats = groups.finish();
+
+    println!("group\ttotal\ttransaction_count\taverage\tmedian\tcount");
+    for stat in &stats {
         println!(
-            "Date: {}, Location: {}, Domain: {}, Count: {}",
-            anomaly.date, anomaly.location, anomaly.domain, anomaly.transaction_count
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            stat.key, stat.total, stat.transaction_count, stat.average, stat.median, stat.count
         );
     }
-}
\ No newline at end of file
+
+    for path in &args.export {
+        write_records(path, args.export_format, &stats)?;
+    }
+    Ok(summary)
+}
+
+// One run of the command line
+fn run(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    // A pipe can only be read once: anomalies (two passes) and the default run (report, then anomalies)
+    // keep the rows of stdin in memory for their later passes
+    let rereads = matches!(cli.command, None | Some(Command::Anomalies(_)));
+    let input = &cli.input.reader(rereads)?;
+
+    let result = match &cli.command {
+        Some(Command::Report(args)) => run_report(input, args),
+        Some(Command::Anomalies(args)) => run_anomalies(input, args),
+        Some(Command::Stats(args)) => run_stats(input, args),
+        None => {
+            // Both read the same rows, so they skip and rewrite the same ones
+            run_report(input, &ReportArgs::default())
+                .and_then(|summary| run_anomalies(input, &AnomalyArgs::default()).map(|_| summary))
+        }
+    };
+    match result {
+        Ok(summary) => report_input(&cli.input, &summary),
+        Err(error) => {
+            // Too many malformed rows: still write the quarantine, to see what they are
+            if let Some(exceeded) = error.downcast_ref::<ErrorRateExceeded>() {
+                report_input(&cli.input, &exceeded.summary)?;
+            }
+            Err(error)
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    run(&Cli::parse())
+}
+
+#[test]
+// Over --max-error-rate the run fails, after writing the skipped rows to the quarantine file
+fn test_error_rate_quarantine() {
+    let base = std::env::temp_dir().join(format!("bank_analysis_test_{}_error_rate", std::process::id()));
+    std::fs::create_dir_all(&base).unwrap();
+    let data = base.join("feed.csv");
+    let quarantine = base.join("quarantine.csv");
+    std::fs::write(&data, "date,domain,location,value,transaction_count\n1/1/2022,RETAIL,Goa,100,1\n1/2/2022,RETAIL,Goa,lots,1\n").unwrap();
+
+    let (data, quarantine) = (data.to_string_lossy().into_owned(), quarantine.to_string_lossy().into_owned());
+    let cli = Cli::parse_from(["bank-analysis", "-i", &data, "--lenient", "--quarantine", &quarantine, "stats"]);
+    let error = run(&cli).unwrap_err().to_string();
+    let written = std::fs::read_to_string(&quarantine).unwrap();
+    std::fs::remove_dir_all(&base).unwrap();
+    assert!(error.starts_with("1 of 2 rows are malformed (50.00%), more than --max-error-rate 0.05; first one: "), "{}", error);
+    assert_eq!(written.lines().count(), 2); // Header and the bad row
+    assert!(written.contains("lots"));
+
+    // The rate is checked when parsing the command line, not when it's too late
+    assert!(Cli::try_parse_from(["bank-analysis", "--max-error-rate=-1", "stats"]).is_err());
+    assert!(Cli::try_parse_from(["bank-analysis", "--max-error-rate", "nan", "stats"]).is_err());
+    assert!(Cli::try_parse_from(["bank-analysis", "--max-error-rate", "1", "stats"]).is_ok());
+}
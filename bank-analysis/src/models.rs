@@ -1,45 +1,282 @@
-use chrono::NaiveDate;
-use serde::Deserialize;
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::money::Money;
 
 // For analysis: convert transactions struct to RegionStats
 // Necessary for graphing regular scatter plots later on
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RegionStats {
     pub region: String,
-    pub total: u64,
-    pub average: f64,
-    pub median: f64,
+    pub total: Money,
+    pub average: Money,
+    pub median: Money,
+    pub count: usize,
+}
+
+// For analysis: convert transactions struct to DomainStats
+// Same statistics as RegionStats, split by business domain (RESTAURANT, RETAIL, ...)
+#[derive(Debug, Serialize)]
+pub struct DomainStats {
+    pub domain: String,
+    pub total: Money,
+    pub average: Money,
+    pub median: Money,
     pub count: usize,
 }
 
-// For analysis: convert transactions struct to MonthStats
+// Size of the time buckets used by the period aggregation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Granularity {
+    Day,
+    Week, // ISO week, starting on Monday
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Granularity {
+    // Name used for chart titles and axis labels
+    pub fn label(&self) -> &'static str {
+        match self {
+            Granularity::Day => "Day",
+            Granularity::Week => "Week",
+            Granularity::Month => "Month",
+            Granularity::Quarter => "Quarter",
+            Granularity::Year => "Year",
+        }
+    }
+}
+
+// Parse from the command line, e.g. "quarter"
+impl FromStr for Granularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "day" => Ok(Granularity::Day),
+            "week" => Ok(Granularity::Week),
+            "month" => Ok(Granularity::Month),
+            "quarter" => Ok(Granularity::Quarter),
+            "year" => Ok(Granularity::Year),
+            _ => Err(format!("unknown granularity '{}' (expected day, week, month, quarter or year)", s)),
+        }
+    }
+}
+
+// One time bucket, identified by its granularity and the first day it covers
+// Keeping the full start date means buckets from different years never merge and sort chronologically
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Period {
+    pub granularity: Granularity,
+    pub start: NaiveDate,
+}
+
+impl Period {
+    // Find the bucket a date falls into
+    pub fn containing(date: NaiveDate, granularity: Granularity) -> Period {
+        let start = match granularity {
+            Granularity::Day => date,
+            Granularity::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Granularity::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+            Granularity::Quarter => NaiveDate::from_ymd_opt(date.year(), (date.month() - 1) / 3 * 3 + 1, 1).unwrap(),
+            Granularity::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        };
+        Period { granularity, start }
+    }
+}
+
+// Label used on the chart axis, e.g. 2022-01-05, 2022-W01, 2022-01, 2022-Q1, 2022
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let date = self.start;
+        match self.granularity {
+            Granularity::Day => write!(f, "{}", date.format("%Y-%m-%d")),
+            Granularity::Week => write!(f, "{}-W{:02}", date.iso_week().year(), date.iso_week().week()),
+            Granularity::Month => write!(f, "{}-{:02}", date.year(), date.month()),
+            Granularity::Quarter => write!(f, "{}-Q{}", date.year(), (date.month() - 1) / 3 + 1),
+            Granularity::Year => write!(f, "{}", date.year()),
+        }
+    }
+}
+
+// Exported as its label (2022-Q1, ...), which also tells the granularity
+impl Serialize for Period {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// For analysis: convert transactions struct to PeriodStats (one row per day, week, month, ...)
 // Necessary for graphing time series later on
-#[derive(Debug)]
-pub struct MonthStats {
-    pub month: u32,        
-    pub value: u64,
-    pub transaction_count: u32,
-    pub average: f64,
-    pub median: f64,
+#[derive(Debug, Serialize)]
+pub struct PeriodStats {
+    pub period: Period,
+    pub value: Money,
+    pub transaction_count: u64,
+    pub average: Money,
+    pub median: Money,
+    pub count: usize,
+}
+
+// Transaction field a group-by can split on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dimension {
+    Domain,
+    Location,
+    Period(Granularity),
+}
+
+// Parse from the command line: "domain", "location", or a granularity for the period
+impl FromStr for Dimension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "domain" => Ok(Dimension::Domain),
+            "location" | "city" => Ok(Dimension::Location),
+            other => other
+                .parse()
+                .map(Dimension::Period)
+                .map_err(|_| format!("unknown dimension '{}' (expected domain, location or a granularity)", s)),
+        }
+    }
+}
+
+// Values of the grouped dimensions for one group, dimensions not grouped on stay None
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupKey {
+    pub domain: Option<String>,
+    pub location: Option<String>,
+    pub period: Option<Period>,
+}
+
+// Label for charts and printing, e.g. RESTAURANT / Goa / 2022-Q1
+impl fmt::Display for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(domain) = &self.domain {
+            parts.push(domain.clone());
+        }
+        if let Some(location) = &self.location {
+            parts.push(location.clone());
+        }
+        if let Some(period) = &self.period {
+            parts.push(period.to_string());
+        }
+
+        if parts.is_empty() {
+            write!(f, "All")
+        } else {
+            write!(f, "{}", parts.join(" / "))
+        }
+    }
+}
+
+// Exported as its label, so the key stays one flat column (csv has no nested fields)
+impl Serialize for GroupKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// For analysis: statistics of one group produced by the generic group-by
+#[derive(Debug, Serialize)]
+pub struct GroupStats {
+    #[serde(rename = "group")] // Same header as the printed table
+    pub key: GroupKey,
+    pub total: Money,
+    pub transaction_count: u64, // Summed over the group, beyond u32 for a year of data
+    pub average: Money,
+    pub median: Money,
     pub count: usize,
 }
 
+// Numeric field of a transaction the anomaly detection can run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Value,
+    TransactionCount,
+    AverageTicket, // value / transaction_count
+}
+
+impl Metric {
+    // Read the metric from one row
+    pub fn of(&self, tx: &Transaction) -> f64 {
+        match self {
+            Metric::Value => tx.value.to_f64(),
+            Metric::TransactionCount => tx.transaction_count as f64,
+            Metric::AverageTicket => {
+                if tx.transaction_count == 0 {
+                    0.0
+                } else {
+                    tx.value.to_f64() / tx.transaction_count as f64
+                }
+            }
+        }
+    }
+
+    // Stable identifier used in exported files
+    pub fn id(&self) -> &'static str {
+        match self {
+            Metric::Value => "value",
+            Metric::TransactionCount => "transaction_count",
+            Metric::AverageTicket => "average_ticket",
+        }
+    }
+
+    // Name used in headers and printing
+    pub fn label(&self) -> &'static str {
+        match self {
+            Metric::Value => "Transaction Value",
+            Metric::TransactionCount => "Transaction Count",
+            Metric::AverageTicket => "Average Ticket",
+        }
+    }
+}
+
+// Parse from the command line: "value", "count" or "average"
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "value" => Ok(Metric::Value),
+            "count" | "transaction_count" => Ok(Metric::TransactionCount),
+            "average" | "average_ticket" => Ok(Metric::AverageTicket),
+            _ => Err(format!("unknown metric '{}' (expected value, count or average)", s)),
+        }
+    }
+}
+
 // Main struct, used as inputs for analysis functions
-#[derive(Debug, Deserialize, Clone)]
+// Built by the parser from the source columns, see parser::Schema
+#[derive(Debug, Clone)]
 pub struct Transaction {
-    #[serde(deserialize_with = "parse_date")]
     pub date: NaiveDate,
     pub domain: String,
     pub location: String,
-    pub value: u64,
+    pub value: Money, // Signed, with up to 4 decimals
     pub transaction_count: u32,
+    pub currency: Option<String>, // ISO code (INR, USD, ...), optional column
+    pub source: Option<Arc<str>>, // File the row was read from, shared by all its rows
 }
 
-// AI: Custom parsing for NaiveDate
-fn parse_date<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let s = String::deserialize(deserializer)?;
-    NaiveDate::parse_from_str(&s, "%m/%d/%Y").map_err(serde::de::Error::custom)
-}
\ No newline at end of file
+// Row for the tests: one transaction, no currency or source, the other fields set with ..Transaction::test_row(...)
+#[cfg(test)]
+impl Transaction {
+    pub(crate) fn test_row(date: (i32, u32, u32), domain: &str, location: &str, value: &str) -> Self {
+        Transaction {
+            date: NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap(),
+            domain: domain.to_string(),
+            location: location.to_string(),
+            value: value.parse().unwrap(),
+            transaction_count: 1,
+            currency: None,
+            source: None,
+        }
+    }
+}
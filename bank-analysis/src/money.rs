@@ -0,0 +1,206 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub};
+use std::str::FromStr;
+
+// Digits kept after the decimal point, enough for cents, mils and converted amounts
+pub const DECIMALS: u32 = 4;
+const SCALE: i128 = 10i128.pow(DECIMALS);
+
+// Signed fixed-point amount, stored as an integer number of 1/10000 units
+// Sums are exact (no float rounding, no u64 limit), refunds and reversals are just negative amounts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Money(i128);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    // Amount from a number of 1/10000 units
+    pub fn from_units(units: i128) -> Self {
+        Money(units)
+    }
+
+    pub fn units(&self) -> i128 {
+        self.0
+    }
+
+    // For the statistics and charts that work on floats (anomaly fences, axis ranges)
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / SCALE as f64
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    // Divide and round half away from zero to the last kept decimal, e.g. an average
+    pub fn div_round(&self, divisor: i128) -> Money {
+        assert!(divisor != 0, "money divided by zero");
+        let quotient = self.0 / divisor;
+        let remainder = self.0 % divisor;
+        if 2 * remainder.abs() >= divisor.abs() {
+            let sign = if (self.0 < 0) != (divisor < 0) { -1 } else { 1 };
+            Money(quotient + sign)
+        } else {
+            Money(quotient)
+        }
+    }
+}
+
+// Whole amounts, e.g. Money::from(1000)
+impl From<i64> for Money {
+    fn from(amount: i64) -> Self {
+        Money(amount as i128 * SCALE)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Money {
+        iter.copied().sum()
+    }
+}
+
+// Shortest exact form: 1000, 12.5, -0.05
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let whole = self.0.abs() / SCALE;
+        let fraction = self.0.abs() % SCALE;
+        if fraction == 0 {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            let digits = format!("{:0width$}", fraction, width = DECIMALS as usize);
+            write!(f, "{}{}.{}", sign, whole, digits.trim_end_matches('0'))
+        }
+    }
+}
+
+// Plain decimal: optional sign, digits, at most `decimals` decimals ("1234", "-12.50", "+.5")
+// Returns the value as an integer number of 1/10^decimals units, more decimals is an error rather than a silent rounding
+// Trailing zeros don't count ("100.000000" from a decimal(18,6) column is fine)
+pub(crate) fn parse_decimal(s: &str, decimals: u32) -> Result<i128, String> {
+    let text = s.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+    let invalid = || format!("invalid amount '{}'", s);
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(invalid());
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        return Err(format!("amount '{}' has more than {} decimals", s, decimals));
+    }
+
+    let whole: i128 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| invalid())? };
+    let fraction: i128 = format!("{:0<width$}", fraction, width = decimals as usize).parse().map_err(|_| invalid())?;
+    let units = whole.checked_mul(10i128.pow(decimals)).and_then(|u| u.checked_add(fraction)).ok_or_else(invalid)?;
+    Ok(if negative { -units } else { units })
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_decimal(s, DECIMALS).map(Money)
+    }
+}
+
+// Written as a decimal string so no reader rounds it through a float
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// Always read from the text, csv would otherwise infer "12.34" as a float before we see it
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MoneyVisitor;
+
+        impl Visitor<'_> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an amount like 1234 or -12.50")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Money, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(MoneyVisitor)
+    }
+}
+
+#[test]
+// Parsing, printing and exact arithmetic
+fn test_money() {
+    let parse = |s: &str| s.parse::<Money>().unwrap();
+
+    assert_eq!(parse("1000"), Money::from(1000));
+    assert_eq!(parse(" -12.50 ").to_string(), "-12.5");
+    assert_eq!(parse("+.05").to_string(), "0.05");
+    assert_eq!(parse("-0.0001").units(), -1);
+    assert!("12.34567".parse::<Money>().is_err());
+    assert_eq!(parse("100.000000"), Money::from(100));
+    assert_eq!(parse("-1.250000000000000000").to_string(), "-1.25");
+    assert!("1.000010".parse::<Money>().is_err());
+    assert!("1,000".parse::<Money>().is_err());
+    assert!("-".parse::<Money>().is_err());
+
+    // 0.1 + 0.2 is exactly 0.3, and far beyond the u64 range
+    assert_eq!(parse("0.1") + parse("0.2"), parse("0.3"));
+    let big: Money = vec![parse("18446744073709551615"); 10].into_iter().sum();
+    assert_eq!(big.to_string(), "184467440737095516150");
+
+    // Rounded half away from zero
+    assert_eq!(parse("10").div_round(3).to_string(), "3.3333");
+    assert_eq!(parse("0.0005").div_round(10).to_string(), "0.0001");
+    assert_eq!(parse("-0.0005").div_round(10).to_string(), "-0.0001");
+}
@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use crate::models::Transaction;
+use crate::parser::load_toml_or_yaml;
+
+// Normalization dictionary, e.g. in TOML:
+// fuzzy_distance = 2            # max typos for a fuzzy match, 0 (default) = exact and case-insensitive only
+// [domain]
+// RESTAURANT = ["RESTRAUNT", "RESTAURENT"]
+// [location]
+// Mumbai = ["Bombay"]
+// Canonical name = list of raw spellings rewritten to it
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dictionary {
+    #[serde(default)]
+    pub fuzzy_distance: usize,
+    #[serde(default)]
+    pub domain: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub location: BTreeMap<String, Vec<String>>,
+}
+
+impl Dictionary {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        load_toml_or_yaml(path, "dictionary")
+    }
+}
+
+// How a raw value was matched to its canonical name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    Exact,           // Listed spelling
+    CaseInsensitive, // Same up to case and spaces
+    Fuzzy,           // Within the allowed number of typos
+}
+
+// One raw value that was rewritten, and on how many rows
+#[derive(Debug, Serialize)]
+pub struct Rewrite {
+    pub field: &'static str,
+    pub raw: String,
+    pub normalized: String,
+    #[serde(rename = "match")]
+    pub kind: MatchKind,
+    pub rows: usize,
+}
+
+// Lower case, single spaces
+fn fold(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+// Edit distance (insertions, deletions, substitutions) between two strings
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Rules of one field (domain or location), with the result of every raw value seen so far
+#[derive(Default, Clone)]
+struct FieldRules {
+    exact: HashMap<String, String>,  // spelling -> canonical
+    folded: HashMap<String, String>, // folded spelling -> canonical
+    seen: HashMap<String, Option<(String, MatchKind)>>,
+}
+
+impl FieldRules {
+    fn add(&mut self, canonical: &str, aliases: &[String]) {
+        for spelling in aliases.iter().map(String::as_str).chain([canonical]) {
+            self.exact.insert(spelling.to_string(), canonical.to_string());
+            self.folded.insert(fold(spelling), canonical.to_string());
+        }
+    }
+
+    fn lookup(&self, raw: &str, max_distance: usize) -> Option<(String, MatchKind)> {
+        if let Some(canonical) = self.exact.get(raw) {
+            return Some((canonical.clone(), MatchKind::Exact));
+        }
+        let folded = fold(raw);
+        if let Some(canonical) = self.folded.get(&folded) {
+            return Some((canonical.clone(), MatchKind::CaseInsensitive));
+        }
+
+        // Fuzzy: short words get fewer typos, and the closest spelling must point to a single canonical name
+        let allowed = max_distance.min(folded.chars().count() / 4);
+        if allowed == 0 {
+            return None;
+        }
+        let mut best: Option<(usize, &String)> = None;
+        let mut ambiguous = false;
+        for (spelling, canonical) in &self.folded {
+            let distance = levenshtein(&folded, spelling);
+            if distance > allowed {
+                continue;
+            }
+            match best {
+                Some((d, c)) if distance == d && c != canonical => ambiguous = true,
+                Some((d, _)) if distance >= d => {}
+                _ => {
+                    best = Some((distance, canonical));
+                    ambiguous = false;
+                }
+            }
+        }
+        match best {
+            Some((_, canonical)) if !ambiguous => Some((canonical.clone(), MatchKind::Fuzzy)),
+            _ => None,
+        }
+    }
+}
+
+// Rewrites the domain and location of every row to their canonical names
+// Raw values not in the dictionary are kept as they are
+// Cloned for every file read in parallel, the rewrites are added up with merge()
+#[derive(Clone)]
+pub struct Normalizer {
+    max_distance: usize,
+    domain: FieldRules,
+    location: FieldRules,
+    rewrites: BTreeMap<(&'static str, String), (String, MatchKind, usize)>,
+}
+
+// Built-in rules, always on
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer::new(&Dictionary::default())
+    }
+}
+
+impl Normalizer {
+    pub fn new(dictionary: &Dictionary) -> Self {
+        let mut normalizer = Normalizer {
+            max_distance: dictionary.fuzzy_distance,
+            domain: FieldRules::default(),
+            location: FieldRules::default(),
+            rewrites: BTreeMap::new(),
+        };
+        normalizer.domain.add("RESTAURANT", &["RESTRAUNT".to_string()]); // Typo of the original dataset
+        for (canonical, aliases) in &dictionary.domain {
+            normalizer.domain.add(canonical, aliases);
+        }
+        for (canonical, aliases) in &dictionary.location {
+            normalizer.location.add(canonical, aliases);
+        }
+        normalizer
+    }
+
+    pub fn apply(&mut self, mut tx: Transaction) -> Transaction {
+        tx.domain = self.normalize("domain", tx.domain);
+        tx.location = self.normalize("location", tx.location);
+        tx
+    }
+
+    fn normalize(&mut self, field: &'static str, raw: String) -> String {
+        let rules = if field == "domain" { &mut self.domain } else { &mut self.location };
+        if !rules.seen.contains_key(&raw) {
+            let found = rules.lookup(&raw, self.max_distance);
+            rules.seen.insert(raw.clone(), found);
+        }
+        match &rules.seen[&raw] {
+            Some((canonical, kind)) if *canonical != raw => {
+                let entry = self.rewrites.entry((field, raw)).or_insert_with(|| (canonical.clone(), *kind, 0));
+                entry.2 += 1;
+                canonical.clone()
+            }
+            _ => raw,
+        }
+    }
+
+    // Add the rewrites counted by another copy
+    pub fn merge(&mut self, other: Normalizer) {
+        for (raw, (normalized, kind, rows)) in other.rewrites {
+            self.rewrites.entry(raw).or_insert((normalized, kind, 0)).2 += rows;
+        }
+    }
+
+    // Every raw value that was changed, by field and raw value
+    pub fn rewrites(&self) -> Vec<Rewrite> {
+        self.rewrites
+            .iter()
+            .map(|((field, raw), (normalized, kind, rows))| Rewrite {
+                field,
+                raw: raw.clone(),
+                normalized: normalized.clone(),
+                kind: *kind,
+                rows: *rows,
+            })
+            .collect()
+    }
+}
+
+#[test]
+// Exact, case-insensitive and fuzzy matches, with the report of what was changed
+fn test_normalizer() {
+    let dictionary: Dictionary = toml::from_str(
+        r#"
+        fuzzy_distance = 2
+        [domain]
+        RESTAURANT = ["RESTAURENT"]
+        [location]
+        Mumbai = ["Bombay"]
+        Bhuj = []
+        Goa = []
+        "#,
+    )
+    .unwrap();
+    let mut normalizer = Normalizer::new(&dictionary);
+    let mut apply = |domain: &str, location: &str| {
+        let tx = normalizer.apply(Transaction::test_row((2022, 1, 1), domain, location, "1"));
+        (tx.domain, tx.location)
+    };
+
+    assert_eq!(apply("RESTRAUNT", "Bombay"), ("RESTAURANT".to_string(), "Mumbai".to_string()));
+    assert_eq!(apply("restaurent", "  mumbai "), ("RESTAURANT".to_string(), "Mumbai".to_string()));
+    assert_eq!(apply("RESTAURNAT", "Mumbay"), ("RESTAURANT".to_string(), "Mumbai".to_string())); // Two typos in the domain, one in the city
+    assert_eq!(apply("RETAIL", "Goa"), ("RETAIL".to_string(), "Goa".to_string())); // Unknown / already canonical
+    assert_eq!(apply("RETAIL", "Gao").1, "Gao"); // Too short for a fuzzy match
+
+    let rewrites = normalizer.rewrites();
+    let bombay = rewrites.iter().find(|r| r.raw == "Bombay").unwrap();
+    assert_eq!((bombay.normalized.as_str(), bombay.kind, bombay.rows), ("Mumbai", MatchKind::Exact, 1));
+    let typo = rewrites.iter().find(|r| r.raw == "Mumbay").unwrap();
+    assert_eq!(typo.kind, MatchKind::Fuzzy);
+    assert!(rewrites.iter().all(|r| r.raw != "Goa" && r.raw != "RETAIL"));
+}
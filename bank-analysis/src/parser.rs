@@ -1,69 +1,773 @@
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::VecDeque;
 use std::error::Error;
-use std::fs::File;
-use csv::ReaderBuilder;
+use std::fmt;
+use std::fs::{read_to_string, File};
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::columnar::{read_arrow_file, read_arrow_stream, read_parquet, stream_arrow_file, stream_arrow_stream, stream_parquet_file};
+use crate::json::{read_json_transactions, stream_json_file};
 use crate::models::Transaction;
+use crate::money::Money;
+
+// Date formats detected from the sampled rows when the schema has none (chrono syntax)
+// Month-first before day-first like the original dataset, a sample that fits both (no day above 12) gets a warning
+const AUTO_DATE_FORMATS: &[&str] = &["%m/%d/%Y", "%Y-%m-%d", "%d/%m/%Y", "%d.%m.%Y", "%Y/%m/%d", "%d-%m-%Y", "%m-%d-%Y", "%d %b %Y", "%b %d %Y"];
+
+// Rows read ahead to detect the date format before the first row is handed out
+pub(crate) const DATE_SAMPLE: usize = 100;
+
+// Input path standing for stdin
+pub const STDIN: &str = "-";
+
+// Source column of every transaction field, a missing entry means the field's own name
+// Header names are compared without case and surrounding spaces
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnMapping {
+    pub date: Option<String>,
+    pub domain: Option<String>,
+    pub location: Option<String>,
+    pub value: Option<String>,
+    pub transaction_count: Option<String>,
+    pub currency: Option<String>,
+}
+
+// Layout of one bank feed, e.g. in TOML:
+// date_formats = ["%d/%m/%Y"]
+// [columns]
+// date = "Txn Date"
+// value = "Amount"
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    #[serde(default)]
+    pub columns: ColumnMapping,
+    #[serde(default)]
+    pub date_formats: Vec<String>, // The one fitting the most sampled rows is used for the whole file, ties go to the first
+}
+
+// Read a settings file (schema, dictionary, dashboard config), the format comes from the extension: .toml, .yaml or .yml
+// `what` names the file in the error message
+pub fn load_toml_or_yaml<T: DeserializeOwned>(path: &str, what: &str) -> Result<T, Box<dyn Error>> {
+    let text = read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let value = match path.rsplit('.').next().map(|e| e.to_lowercase()).as_deref() {
+        Some("toml") => toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?,
+        Some("yaml" | "yml") => serde_yaml_ng::from_str(&text).map_err(|e| format!("{}: {}", path, e))?,
+        _ => return Err(format!("cannot tell the {} format of '{}', use a .toml, .yaml or .yml file", what, path).into()),
+    };
+    Ok(value)
+}
+
+impl Schema {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        load_toml_or_yaml(path, "schema")
+    }
+
+    // Every field with its configured source column, currency (optional) last
+    fn fields(&self) -> [(&'static str, &Option<String>); 6] {
+        let c = &self.columns;
+        [
+            ("date", &c.date),
+            ("domain", &c.domain),
+            ("location", &c.location),
+            ("value", &c.value),
+            ("transaction_count", &c.transaction_count),
+            ("currency", &c.currency),
+        ]
+    }
+
+    // Field read from a source column (or JSON key), None for the columns that are ignored
+    pub(crate) fn field_of(&self, name: &str) -> Option<&'static str> {
+        self.fields()
+            .into_iter()
+            .find(|(field, column)| column.as_deref().unwrap_or(field).trim().eq_ignore_ascii_case(name.trim()))
+            .map(|(field, _)| field)
+    }
+
+    // Rename the source header to the field names, other columns are ignored
+    pub(crate) fn map_headers(&self, headers: &StringRecord) -> Result<StringRecord, Box<dyn Error>> {
+        let fields = self.fields();
+        let mapped: StringRecord = headers.iter().map(|header| self.field_of(header).unwrap_or("")).collect();
+
+        // Currency is optional, the other columns must be there
+        for (field, column) in &fields[..5] {
+            if !mapped.iter().any(|header| header == *field) {
+                return Err(match column {
+                    Some(column) => format!("column '{}' ({}) not found in the header", column, field),
+                    None => format!("column '{}' not found in the header, map it in the schema", field),
+                }
+                .into());
+            }
+        }
+        Ok(mapped)
+    }
+}
+
+// One csv row before the date is parsed
+#[derive(Debug, Deserialize)]
+struct RawRecord {
+    date: String,
+    domain: String,
+    location: String,
+    value: Money,
+    transaction_count: u32,
+    #[serde(default)]
+    currency: Option<String>,
+}
+
+// A row that couldn't be turned into a transaction, with what is needed to find and fix it
+#[derive(Debug, Clone)]
+pub struct RowError {
+    pub line: Option<u64>,   // 1-based line in csv (the header is line 1) and JSON Lines, row number in Parquet, Arrow and JSON arrays
+    pub fields: Vec<String>, // Raw fields as read, empty when the row itself couldn't be read
+    pub reason: String,
+}
+
+impl RowError {
+    fn from_csv(error: &csv::Error) -> Self {
+        let line = error.position().map(|position| position.line());
+        let reason = match error.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(), // Without the position, already in `line`
+            _ => error.to_string(),
+        };
+        RowError { line, fields: Vec::new(), reason }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.reason),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl Error for RowError {}
+
+// Sampled dates fitting several detected formats equally well, e.g. 01/02/2022
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateAmbiguity {
+    pub example: String,
+    pub format: String, // The one used
+    pub others: Vec<String>,
+}
+
+// One date format for the whole file, so no row is read day-first and the next one month-first
+// A row in another format is a RowError
+pub struct DateParser {
+    format: String,
+    ambiguity: Option<DateAmbiguity>, // Reported by the caller, once per run
+}
+
+impl DateParser {
+    // The format that fits the most sampled dates, among the configured ones if any, else among the known ones
+    pub(crate) fn new(configured: &[String], sample: &[&str]) -> Self {
+        let candidates: Vec<&str> = if configured.is_empty() {
+            AUTO_DATE_FORMATS.to_vec()
+        } else {
+            configured.iter().map(String::as_str).collect()
+        };
+        let fits = |format: &str| sample.iter().filter(|date| NaiveDate::parse_from_str(date, format).is_ok()).count();
+        let counts: Vec<usize> = candidates.iter().map(|format| fits(format)).collect();
+        let best = counts.iter().copied().max().unwrap_or_default();
+        if best == 0 {
+            return DateParser { format: candidates[0].to_string(), ambiguity: None }; // Nothing to go by, the rows will tell
+        }
+
+        // Configured formats are in order of preference, detected ones should be unambiguous
+        let mut fitting = candidates.iter().zip(&counts).filter(|(_, count)| **count == best).map(|(format, _)| *format);
+        let format = fitting.next().unwrap_or(candidates[0]);
+        let others: Vec<String> = fitting.map(str::to_string).collect();
+        let ambiguity = (configured.is_empty() && !others.is_empty())
+            .then(|| DateAmbiguity { example: sample[0].to_string(), format: format.to_string(), others });
+        DateParser { format: format.to_string(), ambiguity }
+    }
+
+    pub(crate) fn parse(&self, text: &str) -> Result<NaiveDate, String> {
+        let text = text.trim();
+        NaiveDate::parse_from_str(text, &self.format).map_err(|_| format!("invalid date '{}' (expected {})", text, self.format))
+    }
+}
+
+// Rows of one input format as read (csv records, JSON values, columnar rows), before they become transactions
+pub trait RecordSource {
+    type Record;
+
+    fn next_record(&mut self) -> Option<Self::Record>;
+
+    // Date of the row as text, for the date format detection (None when it is missing or already a date)
+    fn date_text<'r>(&self, record: &'r Self::Record) -> Option<&'r str>;
+
+    fn parse(&self, record: &Self::Record, dates: &DateParser) -> Result<Transaction, RowError>;
+
+    // Error about a row, with its line and raw fields
+    fn row_error(&self, record: &Self::Record, reason: String) -> RowError;
+}
+
+// Streaming iterator over the rows of any input format
+// Reads a few rows ahead to pick the date format, then yields one cleaned transaction at a time so the whole file
+// never sits in memory; bad rows come out as RowError, the stream can go on after them
+pub struct TransactionStream<S: RecordSource> {
+    records: S,
+    pending: VecDeque<S::Record>, // Rows read ahead for the date detection
+    last: Option<S::Record>,      // Row handed out last, for errors found after parsing it
+    dates: DateParser,
+    pub(crate) source: Option<Arc<str>>, // Stamped on every row
+}
+
+impl<S: RecordSource> TransactionStream<S> {
+    pub(crate) fn new(mut records: S, schema: &Schema) -> Self {
+        let pending: VecDeque<S::Record> = std::iter::from_fn(|| records.next_record()).take(DATE_SAMPLE).collect();
+        let sample: Vec<&str> = pending.iter().filter_map(|record| records.date_text(record)).map(str::trim).collect();
+        let dates = DateParser::new(&schema.date_formats, &sample);
+        TransactionStream { records, pending, last: None, dates, source: None }
+    }
+}
+
+// Rows of any input file, plus what the date detection noticed on its sample
+pub trait RowStream: Iterator<Item = Result<Transaction, RowError>> + Send {
+    fn date_ambiguity(&self) -> Option<&DateAmbiguity>;
+
+    // Error about the row handed out last (e.g. no exchange rate for it), with its line and raw fields
+    fn row_error(&self, reason: String) -> RowError;
+}
+
+impl<S: RecordSource + Send> RowStream for TransactionStream<S>
+where
+    S::Record: Send,
+{
+    fn date_ambiguity(&self) -> Option<&DateAmbiguity> {
+        self.dates.ambiguity.as_ref()
+    }
+
+    fn row_error(&self, reason: String) -> RowError {
+        match &self.last {
+            Some(record) => self.records.row_error(record, reason),
+            None => RowError { line: None, fields: Vec::new(), reason },
+        }
+    }
+}
+
+impl<S: RecordSource> Iterator for TransactionStream<S> {
+    type Item = Result<Transaction, RowError>;
 
-// Function to load and read the csv file
-pub fn load_csv_file(url: &str) -> Result<Vec<Transaction>, Box<dyn Error>>{
-    let file = File::open(url)?; // Will return Err if file can’t open
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.pending.pop_front() {
+            Some(record) => record,
+            None => self.records.next_record()?,
+        };
+        let transaction = self.records.parse(&record, &self.dates);
+        self.last = Some(record);
+        Some(transaction.map(|tx| Transaction { source: self.source.clone(), ..clean_transaction(tx) }))
+    }
+}
+
+// Rows of a csv file, with the header mapped to the transaction fields
+pub struct CsvRecords<R: Read> {
+    records: StringRecordsIntoIter<R>,
+    headers: StringRecord,
+    date_column: usize,
+}
+
+impl<R: Read> RecordSource for CsvRecords<R> {
+    type Record = csv::Result<StringRecord>;
+
+    fn next_record(&mut self) -> Option<Self::Record> {
+        self.records.next()
+    }
+
+    fn date_text<'r>(&self, record: &'r Self::Record) -> Option<&'r str> {
+        record.as_ref().ok()?.get(self.date_column)
+    }
+
+    fn parse(&self, result: &Self::Record, dates: &DateParser) -> Result<Transaction, RowError> {
+        let record = result.as_ref().map_err(RowError::from_csv)?;
+        let error = |reason: String| self.row_error(result, reason);
+
+        if record.len() != self.headers.len() {
+            return Err(error(format!("found {} fields, expected {}", record.len(), self.headers.len())));
+        }
+        let raw: RawRecord = record.deserialize(Some(&self.headers)).map_err(|e| error(RowError::from_csv(&e).reason))?;
+        let date = dates.parse(&raw.date).map_err(error)?;
+        Ok(Transaction {
+            date,
+            domain: raw.domain,
+            location: raw.location,
+            value: raw.value,
+            transaction_count: raw.transaction_count,
+            currency: raw.currency,
+            source: None,
+        })
+    }
+
+    fn row_error(&self, record: &Self::Record, reason: String) -> RowError {
+        match record {
+            Ok(record) => RowError {
+                line: record.position().map(|position| position.line()),
+                fields: record.iter().map(str::to_string).collect(),
+                reason,
+            },
+            Err(error) => RowError { reason, ..RowError::from_csv(error) },
+        }
+    }
+}
+
+pub type CsvStream<R> = TransactionStream<CsvRecords<R>>;
+
+// Wrap any reader (file, cursor, ...) into a transaction stream
+pub fn read_transactions<R: Read>(source: R, schema: &Schema) -> Result<CsvStream<R>, Box<dyn Error>> {
     let mut reader = ReaderBuilder::new()
         .has_headers(true) // Skip first row
-        .from_reader(file);
+        .flexible(true) // Rows with a wrong number of fields are reported by the stream, with their content
+        .from_reader(source);
+    let headers = schema.map_headers(reader.headers()?)?;
+    let date_column = headers.iter().position(|header| header == "date").unwrap_or_default();
+    Ok(TransactionStream::new(CsvRecords { records: reader.into_records(), headers, date_column }, schema))
+}
+
+// Compression of an input file, decoded on the fly while streaming
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    // From the first bytes of the file
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if bytes.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    // From the file name, e.g. dump.csv.gz
+    pub fn from_path(path: &str) -> Self {
+        match path.rsplit('.').next().map(|e| e.to_lowercase()).as_deref() {
+            Some("gz" | "gzip") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            Some("bz2") => Compression::Bzip2,
+            _ => Compression::None,
+        }
+    }
+}
+
+// Open a plain or compressed file ("-" for stdin), the magic bytes win over the extension
+pub fn open_input(path: &str) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    let source: Box<dyn Read + Send> = if path == STDIN { Box::new(std::io::stdin()) } else { Box::new(File::open(path)?) }; // Will return Err if file can’t open
+    let mut file = BufReader::new(source);
+    let compression = Compression::from_magic(file.fill_buf()?).unwrap_or_else(|| Compression::from_path(path));
+    Ok(match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(file)), // Also concatenated .gz members
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(file)),
+    })
+}
+
+// Function to open the csv file (plain, .gz, .zst or .bz2) and stream its rows
+pub fn stream_csv_file(url: &str, schema: &Schema) -> Result<CsvStream<Box<dyn Read + Send>>, Box<dyn Error>> {
+    let source = open_input(url).map_err(|e| format!("{}: {}", url, e))?;
+    let mut stream = read_transactions(source, schema).map_err(|e| format!("{}: {}", url, e))?;
+    stream.source = Some(Arc::from(url));
+    Ok(stream)
+}
 
-    let mut column_items = Vec::new();
+// Layout of an input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    Parquet,
+    ArrowFile,   // Arrow IPC file, also Feather v2
+    ArrowStream, // Arrow IPC stream
+    Json,        // JSON Lines or a JSON array of objects
+}
 
-    for result in reader.deserialize() {
-        let mut transaction: Transaction = result?; // Return error if failure
+impl InputFormat {
+    // From the first bytes of the content, None when it looks like csv (or can't be told)
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"PAR1") {
+            Some(InputFormat::Parquet)
+        } else if bytes.starts_with(b"ARROW1") {
+            Some(InputFormat::ArrowFile)
+        } else if bytes.starts_with(&[0xff; 4]) {
+            Some(InputFormat::ArrowStream) // Continuation marker of the first message
+        } else {
+            match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+                Some(b'{' | b'[') => Some(InputFormat::Json),
+                _ => None,
+            }
+        }
+    }
 
-        // Clean up any extra whitespace in the fields
-        transaction.domain = transaction.domain.trim().to_string();
-        transaction.location = transaction.location.trim().to_string();
+    // Binary formats from the first bytes of the file, then the extension (before any compression suffix),
+    // then JSON from the first character; csv by default
+    pub fn detect(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut start = Vec::new();
+        File::open(path)?.take(512).read_to_end(&mut start)?;
+        let sniffed = InputFormat::sniff(&start);
+        if let Some(format @ (InputFormat::Parquet | InputFormat::ArrowFile | InputFormat::ArrowStream)) = sniffed {
+            return Ok(format);
+        }
+        Ok(data_extension(path).and_then(|e| e.parse().ok()).or(sniffed).unwrap_or(InputFormat::Csv))
+    }
+}
+
+// Parse from the command line or a file extension
+impl FromStr for InputFormat {
+    type Err = String;
 
-        if transaction.domain == "RESTRAUNT" {
-            transaction.domain = "RESTAURANT".to_string(); // Replace with the correct term
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "parquet" | "pq" => Ok(InputFormat::Parquet),
+            "arrow" | "feather" | "ipc" => Ok(InputFormat::ArrowFile),
+            "arrows" => Ok(InputFormat::ArrowStream),
+            "json" | "jsonl" | "ndjson" => Ok(InputFormat::Json),
+            _ => Err(format!("unknown input format '{}' (expected csv, parquet, arrow, arrows or json)", s)),
         }
-        column_items.push(transaction);
     }
+}
 
-    Ok(column_items)
+// Extension of the data inside, e.g. "json" for feed.json.gz
+fn data_extension(path: &str) -> Option<String> {
+    let name = path.to_lowercase();
+    let name = [".gz", ".gzip", ".zst", ".zstd", ".bz2"].iter().find_map(|e| name.strip_suffix(e)).unwrap_or(&name);
+    let (_, extension) = name.rsplit_once('.')?;
+    Some(extension.to_string())
+}
+
+// Rows of any input file
+pub type Transactions = Box<dyn RowStream>;
+
+// Stream piped data (stdin), the format is given or told from the (decompressed) content
+// Parquet and Arrow files need random access, they are read into memory first
+pub fn stream_piped(source: Box<dyn Read + Send>, schema: &Schema, format: Option<InputFormat>) -> Result<Transactions, Box<dyn Error>> {
+    let open = || -> Result<Transactions, Box<dyn Error>> {
+        let mut reader = BufReader::new(source);
+        let format = match format {
+            Some(format) => format,
+            None => InputFormat::sniff(reader.fill_buf()?).unwrap_or(InputFormat::Csv),
+        };
+        let label = Some(Arc::from(STDIN));
+        Ok(match format {
+            InputFormat::Csv => {
+                let mut stream = read_transactions(reader, schema)?;
+                stream.source = label;
+                Box::new(stream)
+            }
+            InputFormat::Json => {
+                let mut stream = read_json_transactions(reader, schema)?;
+                stream.source = label;
+                Box::new(stream)
+            }
+            InputFormat::ArrowStream => {
+                let mut stream = read_arrow_stream(reader, schema)?;
+                stream.source = label;
+                Box::new(stream)
+            }
+            InputFormat::Parquet | InputFormat::ArrowFile => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                let mut stream = match format {
+                    InputFormat::Parquet => read_parquet(bytes, schema)?,
+                    _ => read_arrow_file(Cursor::new(bytes), schema)?,
+                };
+                stream.source = label;
+                Box::new(stream)
+            }
+        })
+    };
+    open().map_err(|e| format!("stdin: {}", e).into())
+}
+
+// Open a csv, Parquet, Arrow or JSON file ("-" for stdin) and stream its rows, the format is detected unless given
+pub fn stream_file(path: &str, schema: &Schema, format: Option<InputFormat>) -> Result<Transactions, Box<dyn Error>> {
+    if path == STDIN {
+        return stream_piped(open_input(STDIN)?, schema, format);
+    }
+    let format = match format {
+        Some(format) => format,
+        None => InputFormat::detect(path).map_err(|e| format!("{}: {}", path, e))?,
+    };
+    Ok(match format {
+        InputFormat::Csv => Box::new(stream_csv_file(path, schema)?),
+        InputFormat::Parquet => Box::new(stream_parquet_file(path, schema)?),
+        InputFormat::ArrowFile => Box::new(stream_arrow_file(path, schema)?),
+        InputFormat::ArrowStream => Box::new(stream_arrow_stream(path, schema)?),
+        InputFormat::Json => Box::new(stream_json_file(path, schema)?),
+    })
+}
+
+// Files picked up from a directory: csv, Parquet, Arrow and JSON, possibly compressed
+fn is_input_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    path.is_file() && !name.starts_with('.') && data_extension(name).is_some_and(|e| e.parse::<InputFormat>().is_ok())
+}
+
+// Turn the --input arguments into a list of files, in a stable (sorted) order within each argument
+// A directory gives its data files (not recursive), a pattern like feeds/*/2022-*.csv.gz its matches,
+// anything else is taken as a file name, "-" as stdin
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    if inputs.iter().filter(|input| *input == STDIN).count() > 1 {
+        return Err("stdin ('-') can only be read once".into());
+    }
+    let mut files = Vec::new();
+    for input in inputs {
+        let mut matched: Vec<String> = if Path::new(input).is_dir() {
+            std::fs::read_dir(input)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|path| is_input_file(path))
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect()
+        } else if input.contains(['*', '?', '[']) {
+            glob::glob(input)?
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|path| path.is_file())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect()
+        } else {
+            vec![input.clone()]
+        };
+
+        if matched.is_empty() {
+            return Err(format!("no input files found for '{}'", input).into());
+        }
+        matched.sort();
+        files.extend(matched);
+    }
+    Ok(files)
+}
+
+// Clean up a freshly parsed row
+pub(crate) fn clean_transaction(mut transaction: Transaction) -> Transaction {
+    // Clean up any extra whitespace in the fields
+    transaction.domain = transaction.domain.trim().to_string();
+    transaction.location = transaction.location.trim().to_string();
+
+    transaction.currency = transaction.currency.map(|c| c.trim().to_uppercase()).filter(|c| !c.is_empty());
+    transaction // Spelling variants are fixed afterwards by the normalize module
 }
 
 #[test]
 fn test_load_file() {
+    use crate::normalize::Normalizer;
     use std::io::Cursor; // Pretend string as file for reading
 
     // Sample data
     let data = "\
 date,domain,location,value,transaction_count
 1/1/2022,RESTRAUNT,Goa,1000,2
+1/2/2022,RETAIL,Bhuj,-12.50,1
 ";
 
-    // Initialize cursor & reader
+    // Initialize cursor & stream
     let cursor = Cursor::new(data);
 
-    let mut reader = ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(cursor);
+    let mut normalizer = Normalizer::default(); // Built-in RESTRAUNT fix
+    let results: Vec<Transaction> = read_transactions(cursor, &Schema::default())
+        .unwrap()
+        .map(|result| normalizer.apply(result.expect("Failed to parse row"))) // Program crash if fail
+        .collect();
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].domain, "RESTAURANT");
+    assert_eq!(results[0].location, "Goa");
+    assert_eq!(results[0].value, 1000.into());
+    assert_eq!(results[0].transaction_count, 2);
+    assert_eq!(results[1].value.to_string(), "-12.5"); // Refund with cents
+}
 
-    let mut results = Vec::new();
+#[test]
+// One date format per file: a day-first month whose sample only has days up to 12 is read month-first throughout,
+// with the ambiguity reported, the later days are errors rather than switching format midway
+fn test_date_format_per_file() {
+    let mut data = String::from("date,domain,location,value,transaction_count\n");
+    for day in 1..=31 {
+        for _ in 0..10 {
+            data.push_str(&format!("{:02}/01/2022,RETAIL,Goa,1,1\n", day));
+        }
+    }
 
-    for result in reader.deserialize() {
-        let mut transaction: Transaction = result.expect("Failed to parse row"); // Program crash if fail
+    // The sample (days 1 to 10) fits both orders: read month-first throughout, the 13th onwards are errors
+    let stream = read_transactions(data.as_bytes(), &Schema::default()).unwrap();
+    let ambiguity = stream.date_ambiguity().unwrap();
+    assert_eq!((ambiguity.example.as_str(), ambiguity.format.as_str()), ("01/01/2022", "%m/%d/%Y"));
+    assert_eq!(ambiguity.others, vec!["%d/%m/%Y"]);
+    let results: Vec<_> = stream.collect();
+    assert_eq!(results[0].as_ref().unwrap().date, NaiveDate::from_ymd_opt(2022, 1, 1).unwrap());
+    assert_eq!(results[10].as_ref().unwrap().date, NaiveDate::from_ymd_opt(2022, 2, 1).unwrap());
+    assert!(results[..120].iter().all(Result::is_ok));
+    let error = results[120].as_ref().unwrap_err();
+    assert_eq!(error.reason, "invalid date '13/01/2022' (expected %m/%d/%Y)");
+    assert_eq!(results.iter().filter(|result| result.is_err()).count(), 190);
 
-        // Remove white spaces
-        transaction.domain = transaction.domain.trim().to_string();
-        transaction.location = transaction.location.trim().to_string();
+    // With the format given every row is in January
+    let schema = Schema { date_formats: vec!["%d/%m/%Y".to_string()], ..Schema::default() };
+    assert!(read_transactions(data.as_bytes(), &schema).unwrap().date_ambiguity().is_none());
+    let dates: Vec<NaiveDate> = read_transactions(data.as_bytes(), &schema).unwrap().map(|result| result.unwrap().date).collect();
+    assert_eq!(dates.len(), 310);
+    assert!(dates.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(dates[309], NaiveDate::from_ymd_opt(2022, 1, 31).unwrap());
+}
 
-        if transaction.domain == "RESTRAUNT" {
-            transaction.domain = "RESTAURANT".to_string();
-        }
+#[test]
+// Another bank feed: own headers, day-first dates, extra columns
+fn test_schema_mapping() {
+    let data = "\
+Txn Date,Branch,Sector,Amount,Txns,Reference
+05/01/2022,Goa,RETAIL,100.25,2,A1
+25/01/2022,Bhuj,RETAIL,-3,1,A2
+";
+    let schema: Schema = toml::from_str(
+        r#"
+        [columns]
+        date = "txn date"
+        location = "Branch"
+        domain = "Sector"
+        value = "Amount"
+        transaction_count = "Txns"
+        "#,
+    )
+    .unwrap();
+
+    // The 25th only fits day-first, so the 5th is read as January too
+    let results: Vec<Transaction> = read_transactions(data.as_bytes(), &schema).unwrap().map(Result::unwrap).collect();
+    assert_eq!(results[0].date, NaiveDate::from_ymd_opt(2022, 1, 5).unwrap());
+    assert_eq!(results[1].date, NaiveDate::from_ymd_opt(2022, 1, 25).unwrap());
+    assert_eq!(results[0].location, "Goa");
+    assert_eq!(results[0].value.to_string(), "100.25");
+
+    // ISO dates without any configuration
+    let iso = "date,domain,location,value,transaction_count\n2022-03-04,RETAIL,Goa,1,1\n";
+    let row = read_transactions(iso.as_bytes(), &Schema::default()).unwrap().next().unwrap().unwrap();
+    assert_eq!(row.date, NaiveDate::from_ymd_opt(2022, 3, 4).unwrap());
+
+    // A missing column is reported with its source name
+    let error = read_transactions("when,x\n".as_bytes(), &schema).err().unwrap();
+    assert!(error.to_string().contains("'txn date'"));
+}
+
+#[test]
+// Bad rows come out as errors with their line and content, the stream goes on after them
+fn test_row_errors() {
+    let data = "\
+date,domain,location,value,transaction_count
+1/1/2022,RETAIL,Goa,abc,1
+1/2/2022,RETAIL,Goa,5
+1/3/2022,RETAIL,Goa,7,1
+1/4/2022,RETAIL,Goa,7.500000,1
+1/5/2022,RETAIL,Goa,7.500001,1
+";
+    let results: Vec<_> = read_transactions(data.as_bytes(), &Schema::default()).unwrap().collect();
+    assert_eq!(results.len(), 5);
+
+    let error = results[0].as_ref().unwrap_err();
+    assert_eq!(error.line, Some(2));
+    assert_eq!(error.fields, vec!["1/1/2022", "RETAIL", "Goa", "abc", "1"]);
+    assert!(error.reason.contains("abc"));
+
+    let error = results[1].as_ref().unwrap_err();
+    assert_eq!(error.to_string(), "line 3: found 4 fields, expected 5");
+    assert!(results[2].is_ok());
+
+    // Extra decimals are fine as long as they are zeros
+    assert_eq!(results[3].as_ref().unwrap().value.to_string(), "7.5");
+    assert!(results[4].as_ref().unwrap_err().reason.contains("more than 4 decimals"));
+}
 
-        results.push(transaction);
+#[test]
+// The same rows through every compression, detected from the content
+fn test_compressed_inputs() {
+    use std::io::Write;
+
+    let data = "date,domain,location,value,transaction_count\n1/1/2022,RETAIL,Goa,1000,2\n1/2/2022,RETAIL,Bhuj,5,1\n";
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(data.as_bytes()).unwrap();
+    let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    bzip2.write_all(data.as_bytes()).unwrap();
+
+    let encoded = [
+        ("plain", data.as_bytes().to_vec()),
+        ("gzip", gzip.finish().unwrap()),
+        ("zstd", zstd::encode_all(data.as_bytes(), 0).unwrap()),
+        ("bzip2", bzip2.finish().unwrap()),
+    ];
+    for (name, bytes) in encoded {
+        // No extension, so only the magic bytes can tell
+        let path = std::env::temp_dir().join(format!("bank_analysis_test_{}_{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let results: Vec<Transaction> = stream_csv_file(path.to_str().unwrap(), &Schema::default())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(results.len(), 2, "{}", name);
+        assert_eq!(results[1].location, "Bhuj", "{}", name);
     }
 
-    assert_eq!(results.len(), 1);
-    assert_eq!(results[0].domain, "RESTAURANT");
-    assert_eq!(results[0].location, "Goa");
-    assert_eq!(results[0].value, 1000);
-    assert_eq!(results[0].transaction_count, 2);
-}   
\ No newline at end of file
+    assert_eq!(Compression::from_path("2022-01.csv.ZST"), Compression::Zstd);
+}
+
+#[test]
+// Directories and patterns expand to their csv files, each row knows its file
+fn test_expand_inputs() {
+    let dir = std::env::temp_dir().join(format!("bank_analysis_test_{}_feeds", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = "date,domain,location,value,transaction_count\n1/1/2022,RETAIL,Goa,1000,2\n";
+    for name in ["goa_2022-01-02.csv", "goa_2022-01-01.csv", "notes.txt"] {
+        std::fs::write(dir.join(name), data).unwrap();
+    }
+    let dir_name = dir.to_str().unwrap().to_string();
+
+    let files = expand_inputs(std::slice::from_ref(&dir_name)).unwrap();
+    let names: Vec<&str> = files.iter().map(|f| f.rsplit(['/', '\\']).next().unwrap()).collect();
+    assert_eq!(names, vec!["goa_2022-01-01.csv", "goa_2022-01-02.csv"]);
+
+    let pattern = format!("{}/*-02.csv", dir_name);
+    let files = expand_inputs(&[pattern]).unwrap();
+    assert_eq!(files.len(), 1);
+    let row = stream_csv_file(&files[0], &Schema::default()).unwrap().next().unwrap().unwrap();
+    assert_eq!(row.source.as_deref(), Some(files[0].as_str()));
+
+    assert!(expand_inputs(&[format!("{}/*.parquet", dir_name)]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+// Piped data has no name, the format comes from the (decompressed) content
+fn test_piped_input() {
+    let csv = "date,domain,location,value,transaction_count\n1/1/2022,RETAIL,Goa,1000,2\n";
+    let json = r#"  {"date": "2022-01-01", "domain": "RETAIL", "location": "Goa", "value": 1000, "transaction_count": 2}"#;
+
+    for (name, bytes) in [("csv", csv.as_bytes().to_vec()), ("jsonl", json.as_bytes().to_vec())] {
+        let rows: Vec<Transaction> = stream_piped(Box::new(Cursor::new(bytes)), &Schema::default(), None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 1, "{}", name);
+        assert_eq!((rows[0].value, rows[0].source.as_deref()), (1000.into(), Some(STDIN)), "{}", name);
+    }
+
+    assert_eq!(InputFormat::sniff(b"PAR1\x15\x04"), Some(InputFormat::Parquet));
+    assert_eq!(InputFormat::sniff(b"date,domain"), None);
+}